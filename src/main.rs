@@ -1,11 +1,20 @@
+mod password;
 mod pueue;
 mod settings;
 mod tracing;
 mod web;
 
-use std::time::Duration;
+use std::{
+    io::{IsTerminal, Write, stderr, stdin},
+    time::Duration,
+};
 
-use crate::{pueue::get_pueue_client, settings::Settings, web::run_web_server};
+use crate::{
+    password::{generate_token, hash_password, hash_token},
+    pueue::{get_pueue_client, sync_groups},
+    settings::Settings,
+    web::run_web_server,
+};
 
 pub(crate) mod internal_prelude {
     pub use color_eyre::{
@@ -20,6 +29,20 @@ use crate::internal_prelude::*;
 
 #[actix_web::main]
 async fn main() -> Result<()> {
+    match std::env::args().nth(1).as_deref() {
+        None => (),
+        Some("hash-password") => return print_password_hash(),
+        Some("generate-token") => {
+            let token = generate_token();
+            println!("token: {token}");
+            println!("token_hash: {}", hash_token(&token));
+            return Ok(());
+        }
+        Some(argument) => {
+            bail!("Unknown argument: {argument}. Available: hash-password, generate-token")
+        }
+    }
+
     let settings = Settings::new()?;
     tracing::install_tracing(1)?;
 
@@ -32,6 +55,28 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Read a password from stdin and print its hash, so it can be used for basic auth users.
+fn print_password_hash() -> Result<()> {
+    if stdin().is_terminal() {
+        eprint!("Password: ");
+        stderr().flush()?;
+    }
+
+    let mut password = String::new();
+    stdin()
+        .read_line(&mut password)
+        .context("Failed to read password from stdin")?;
+    // Only remove the line break, whitespace might be part of the password.
+    let password = password.trim_end_matches(['\n', '\r']);
+    if password.is_empty() {
+        bail!("The password must not be empty");
+    }
+
+    println!("{}", hash_password(password)?);
+
+    Ok(())
+}
+
 async fn wait_for_pueue(settings: &Settings) -> Result<()> {
     // Total time limit (5 minutes)
     let max_duration = Duration::from_secs(300);
@@ -41,10 +86,10 @@ async fn wait_for_pueue(settings: &Settings) -> Result<()> {
     loop {
         info!("Checking if a Pueue daemon is available...");
 
-        match get_pueue_client(settings).await {
-            Ok(_) => {
+        match get_pueue_client().await {
+            Ok(mut client) => {
                 info!("Pueue daemon is available!");
-                return Ok(());
+                return sync_groups(&mut client, settings).await;
             }
             Err(err) => {
                 info!("Failed to connect: {err:?}");
@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{LazyLock, Mutex},
+};
+
+use argon2::{
+    Argon2,
+    Params,
+    PasswordHash,
+    PasswordHasher,
+    PasswordVerifier,
+    password_hash::{
+        SaltString,
+        rand_core::{OsRng, RngCore},
+    },
+};
+use bcrypt::HashParts;
+use sha2::{Digest, Sha256};
+
+use crate::internal_prelude::*;
+
+/// Hash a password with argon2id and a random salt.
+/// The result is a PHC string, which can be used as `password_hash` of a basic auth user.
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|error| eyre!("Failed to hash password: {error}"))?;
+
+    Ok(hash.to_string())
+}
+
+/// Check that a hash is either a valid argon2 PHC string or a valid bcrypt hash.
+pub fn validate_password_hash(hash: &str) -> Result<()> {
+    if hash.starts_with("$argon2") {
+        let hash =
+            PasswordHash::new(hash).map_err(|error| eyre!("Invalid argon2 hash: {error}"))?;
+        if hash.hash.is_none() || hash.salt.is_none() {
+            bail!("Invalid argon2 hash: Missing salt or hash");
+        }
+    } else if hash.starts_with("$2") {
+        HashParts::from_str(hash).map_err(|error| eyre!("Invalid bcrypt hash: {error}"))?;
+    } else {
+        bail!("Unsupported password hash. Only argon2 and bcrypt hashes are supported");
+    }
+
+    Ok(())
+}
+
+/// Verify a password against an argon2 or bcrypt hash.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return false;
+        };
+        return Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+    }
+
+    bcrypt::verify(password, hash).unwrap_or(false)
+}
+
+/// Hashes of random passwords, which are verified instead, if a user doesn't exist.
+/// That way, unknown users take as long as known users and user names can't be guessed by timing.
+/// The hashes are keyed by the scheme and cost of the hashes they imitate.
+static DUMMY_HASHES: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Get a hash of a random password with the same scheme and cost as `hash`.
+/// The dummy hash is only generated once, so this should be called on startup.
+pub fn get_dummy_hash(hash: &str) -> String {
+    // The salt and the hash itself are the last parts. Everything else determines the cost.
+    let parts = if hash.starts_with("$argon2") { 3 } else { 2 };
+    let key = hash.rsplitn(parts, '$').last().unwrap_or_default();
+
+    let mut dummy_hashes = DUMMY_HASHES.lock().unwrap();
+    dummy_hashes
+        .entry(key.to_string())
+        .or_insert_with(|| {
+            generate_dummy_hash(hash).unwrap_or_else(|| {
+                hash_password(&generate_token()).expect("Couldn't hash dummy password")
+            })
+        })
+        .clone()
+}
+
+/// Hash a random password with the scheme and cost of `hash`.
+fn generate_dummy_hash(hash: &str) -> Option<String> {
+    let password = generate_token();
+    if hash.starts_with("$argon2") {
+        let hash = PasswordHash::new(hash).ok()?;
+        let params = Params::try_from(&hash).ok()?;
+        let salt = SaltString::generate(&mut OsRng);
+        let dummy = Argon2::default()
+            .hash_password_customized(
+                password.as_bytes(),
+                Some(hash.algorithm),
+                hash.version,
+                params,
+                &salt,
+            )
+            .ok()?;
+        return Some(dummy.to_string());
+    }
+
+    let cost = HashParts::from_str(hash).ok()?.get_cost();
+    bcrypt::hash(password, cost).ok()
+}
+
+/// Verify a password against the hash of a user. If there's no such user, a dummy hash with the
+/// same scheme and cost as `other_hash` is verified instead, which always fails.
+/// `other_hash` is the hash of any other configured user.
+pub fn verify_user_password(password: &str, hash: Option<&str>, other_hash: &str) -> bool {
+    match hash {
+        Some(hash) => verify_password(password, hash),
+        None => {
+            verify_password(password, &get_dummy_hash(other_hash));
+            false
+        }
+    }
+}
+
+/// Generate a random API token.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hash an API token with sha256.
+/// API tokens are random, so there's no need for a slow password hash.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Generated hashes can be verified
+    fn test_hash_password() {
+        let hash = hash_password("pass:word").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(validate_password_hash(&hash).is_ok());
+
+        assert!(verify_password("pass:word", &hash));
+        assert!(!verify_password("pass", &hash));
+    }
+
+    #[test]
+    /// Bcrypt hashes, e.g. from htpasswd, are supported as well
+    fn test_bcrypt() {
+        let hash = bcrypt::hash("password", 4).unwrap();
+        assert!(validate_password_hash(&hash).is_ok());
+
+        assert!(verify_password("password", &hash));
+        assert!(!verify_password("wrong", &hash));
+    }
+
+    #[test]
+    /// Unknown users are never verified
+    fn test_verify_user_password() {
+        let hash = hash_password("password").unwrap();
+        assert!(verify_user_password("password", Some(&hash), &hash));
+        assert!(!verify_user_password("password", None, &hash));
+    }
+
+    #[test]
+    /// Dummy hashes use the same scheme and cost as the hashes of the users
+    fn test_dummy_hash() {
+        let bcrypt_hash = bcrypt::hash("password", 5).unwrap();
+        let dummy = get_dummy_hash(&bcrypt_hash);
+        assert_eq!(HashParts::from_str(&dummy).unwrap().get_cost(), 5);
+        assert_eq!(get_dummy_hash(&bcrypt_hash), dummy);
+
+        let argon2_hash = hash_password("password").unwrap();
+        let dummy = get_dummy_hash(&argon2_hash);
+        let (prefix, _) = argon2_hash.rsplit_once('$').unwrap();
+        let (prefix, _) = prefix.rsplit_once('$').unwrap();
+        assert!(dummy.starts_with(prefix));
+        assert_ne!(dummy, argon2_hash);
+    }
+
+    #[test]
+    /// Generated tokens are random and are hashed with sha256
+    fn test_generate_token() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+
+        assert_eq!(
+            hash_token("token"),
+            "3c469e9d6c5875d37a43f353d4f88e61fcf812c66eee3457465a40b0da4153e0"
+        );
+    }
+
+    #[test]
+    /// Plaintext passwords and unknown hashes are refused
+    fn test_invalid_hash() {
+        assert!(validate_password_hash("password").is_err());
+        assert!(validate_password_hash("$argon2id$broken").is_err());
+        assert!(validate_password_hash("$2b$04$tooshort").is_err());
+        assert!(!verify_password("password", "password"));
+    }
+}
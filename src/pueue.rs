@@ -1,38 +1,101 @@
-use pueue_lib::{Client, message::GroupRequest, prelude::*, secret::read_shared_secret};
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    time::{Duration, Instant},
+};
+
+use pueue_lib::{
+    Client,
+    message::{GroupRequest, LogRequest, ParallelRequest, TaskSelection},
+    prelude::*,
+    secret::read_shared_secret,
+};
+use snap::read::FrameDecoder;
 
 use crate::{internal_prelude::*, settings::Settings as InternalSettings};
 
-pub async fn get_pueue_client(settings: &InternalSettings) -> Result<Client> {
+pub async fn get_pueue_client() -> Result<Client> {
     // Try to read settings from the default configuration file.
     let (pueue_settings, _) = Settings::read(&None)?;
 
     let secret = read_shared_secret(&pueue_settings.shared.shared_secret_path())?;
 
     // Create client to talk with the daemon and connect.
-    let mut client = Client::new(pueue_settings.shared.try_into()?, &secret, true)
+    let client = Client::new(pueue_settings.shared.try_into()?, &secret, true)
         .await
         .context("Failed to initialize client.")?;
 
+    Ok(client)
+}
+
+/// Bring Pueue's groups in sync with our settings. This only happens on startup, so changes that
+/// are made via `pueue parallel` afterwards are kept.
+pub async fn sync_groups(client: &mut Client, settings: &InternalSettings) -> Result<()> {
     // Every webhook can run in a separate pueue group.
-    // Get the currently available Pueue groups, so we know which groups we have to create.
-    let state = get_state(&mut client).await?;
-    let mut existing_groups: Vec<String> = state.groups.keys().cloned().collect();
+    // Get the currently available Pueue groups, so we know which groups we have to create or
+    // update.
+    let state = get_state(client).await?;
 
-    // Create all missing groups in Pueue.
+    for message in get_group_requests(settings, &state.groups) {
+        client.send_request(message.clone()).await?;
+        let response = client.receive_response().await?;
+        if let Response::Failure(error) = response {
+            bail!("Failed to sync pueue group with {message:?}: {error}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Determine all requests that are needed to bring Pueue's groups in sync with our settings.
+///
+/// - Groups used by webhooks or declared in the `groups` section are created, if missing.
+/// - Existing groups whose `parallel_tasks` differ from the `groups` section are updated.
+fn get_group_requests(
+    settings: &InternalSettings,
+    existing_groups: &BTreeMap<String, Group>,
+) -> Vec<Request> {
+    let mut requests = Vec::new();
+
+    // Collect all groups we need, while preserving a deterministic order.
+    let mut needed_groups: Vec<&String> = settings.groups.keys().collect();
+    needed_groups.sort();
     for webhook in settings.webhooks.iter() {
-        if !existing_groups.contains(&webhook.pueue_group) {
-            info!("Create new pueue group {}", webhook.pueue_group);
-
-            let message = Request::Group(GroupRequest::Add {
-                name: webhook.pueue_group.clone(),
-                parallel_tasks: None,
-            });
-            client.send_request(message).await?;
-            existing_groups.push(webhook.pueue_group.clone());
+        if !needed_groups.contains(&&webhook.pueue_group) {
+            needed_groups.push(&webhook.pueue_group);
         }
     }
 
-    Ok(client)
+    for name in needed_groups {
+        let parallel_tasks = settings.groups.get(name).map(|group| group.parallel_tasks);
+
+        match existing_groups.get(name) {
+            None => {
+                info!("Create new pueue group {name}");
+                requests.push(Request::Group(GroupRequest::Add {
+                    name: name.clone(),
+                    parallel_tasks,
+                }));
+            }
+            Some(group) => {
+                let Some(parallel_tasks) = parallel_tasks else {
+                    continue;
+                };
+                if group.parallel_tasks != parallel_tasks {
+                    info!(
+                        "Update parallel tasks of pueue group {name} from {} to {parallel_tasks}",
+                        group.parallel_tasks
+                    );
+                    requests.push(Request::Parallel(ParallelRequest {
+                        parallel_tasks,
+                        group: name.clone(),
+                    }));
+                }
+            }
+        }
+    }
+
+    requests
 }
 
 // This is a helper function for easy retrieval of the current daemon state.
@@ -47,3 +110,181 @@ pub async fn get_state(client: &mut Client) -> Result<State> {
         _ => unreachable!(),
     }
 }
+
+/// Get a single task from the daemon's state.
+pub async fn get_task(client: &mut Client, task_id: usize) -> Result<Option<Task>> {
+    let mut state = get_state(client).await?;
+
+    Ok(state.tasks.remove(&task_id))
+}
+
+/// Wait for a task to finish.
+/// Returns the finished task or `None`, if the task didn't finish within the timeout.
+pub async fn wait_for_task(
+    client: &mut Client,
+    task_id: usize,
+    timeout: Duration,
+) -> Result<Option<Task>> {
+    let start = Instant::now();
+
+    loop {
+        let Some(task) = get_task(client, task_id).await? else {
+            bail!("Task {task_id} has been removed while waiting for it");
+        };
+        if task.is_done() {
+            return Ok(Some(task));
+        }
+
+        if start.elapsed() >= timeout {
+            return Ok(None);
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// The log of a single task.
+pub struct TaskLog {
+    pub task: Task,
+    pub output: String,
+    /// Whether the output has been truncated by the daemon.
+    pub output_complete: bool,
+}
+
+/// Get a single task and its log output from the daemon.
+/// If `lines` is set, only the last `lines` lines of output are returned.
+pub async fn get_task_log(
+    client: &mut Client,
+    task_id: usize,
+    lines: Option<usize>,
+) -> Result<Option<TaskLog>> {
+    client
+        .send_request(LogRequest {
+            tasks: TaskSelection::TaskIds(vec![task_id]),
+            send_logs: true,
+            lines,
+        })
+        .await?;
+    let response = client.receive_response().await?;
+
+    let mut logs = match response {
+        Response::Log(logs) => logs,
+        Response::Failure(error) => bail!("Failed to get log: {error}"),
+        _ => unreachable!(),
+    };
+    let Some(log) = logs.remove(&task_id) else {
+        return Ok(None);
+    };
+
+    let output = match log.output {
+        Some(compressed) => decompress_log(&compressed)?,
+        None => String::new(),
+    };
+
+    Ok(Some(TaskLog {
+        task: log.task,
+        output,
+        output_complete: log.output_complete,
+    }))
+}
+
+/// The daemon compresses log output with snappy.
+fn decompress_log(compressed: &[u8]) -> Result<String> {
+    let mut output = Vec::new();
+    FrameDecoder::new(compressed)
+        .read_to_end(&mut output)
+        .context("Failed to decompress task log")?;
+
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Write, path::PathBuf};
+
+    use snap::write::FrameEncoder;
+
+    use super::*;
+    use crate::settings::{PueueGroup, Webhook};
+
+    fn setup_settings() -> InternalSettings {
+        let webhook = |name: &str, group: &str| Webhook {
+            name: name.to_string(),
+            command: Some("/bin/true".to_string()),
+            cwd: PathBuf::from("/tmp"),
+            pueue_group: group.to_string(),
+            ..Default::default()
+        };
+
+        InternalSettings {
+            webhooks: vec![webhook("deploy", "deploy"), webhook("ls", "webhook")],
+            groups: HashMap::from([
+                ("deploy".to_string(), PueueGroup { parallel_tasks: 1 }),
+                ("thumbnails".to_string(), PueueGroup { parallel_tasks: 8 }),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn group(parallel_tasks: usize) -> Group {
+        Group {
+            status: GroupStatus::Running,
+            parallel_tasks,
+        }
+    }
+
+    #[test]
+    /// Missing groups are created with their configured parallelism
+    fn test_create_missing_groups() {
+        let settings = setup_settings();
+        let requests = get_group_requests(&settings, &BTreeMap::new());
+
+        assert_eq!(
+            requests,
+            vec![
+                Request::Group(GroupRequest::Add {
+                    name: "deploy".to_string(),
+                    parallel_tasks: Some(1),
+                }),
+                Request::Group(GroupRequest::Add {
+                    name: "thumbnails".to_string(),
+                    parallel_tasks: Some(8),
+                }),
+                Request::Group(GroupRequest::Add {
+                    name: "webhook".to_string(),
+                    parallel_tasks: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    /// Existing groups are only updated, if their parallelism differs from the configuration
+    fn test_reconcile_existing_groups() {
+        let settings = setup_settings();
+        let existing = BTreeMap::from([
+            ("deploy".to_string(), group(4)),
+            ("thumbnails".to_string(), group(8)),
+            ("webhook".to_string(), group(3)),
+        ]);
+        let requests = get_group_requests(&settings, &existing);
+
+        assert_eq!(
+            requests,
+            vec![Request::Parallel(ParallelRequest {
+                parallel_tasks: 1,
+                group: "deploy".to_string(),
+            })]
+        );
+    }
+
+    #[test]
+    /// Log output that's been compressed by the daemon can be read
+    fn test_decompress_log() {
+        let mut encoder = FrameEncoder::new(Vec::new());
+        encoder.write_all(b"line 1\nline 2\n").unwrap();
+        let compressed = encoder.into_inner().unwrap();
+
+        assert_eq!(decompress_log(&compressed).unwrap(), "line 1\nline 2\n");
+        assert!(decompress_log(b"not snappy").is_err());
+    }
+}
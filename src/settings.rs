@@ -1,51 +1,862 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io::BufReader,
     path::{Path, PathBuf},
 };
 
 use actix_web::error::{Error, ErrorBadRequest};
+use chrono::{DateTime, Utc};
+use globset::Glob;
+use ipnet::IpNet;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use regex::Regex;
 use serde::Deserialize;
 
-use crate::internal_prelude::*;
+use crate::{
+    internal_prelude::*,
+    password::{get_dummy_hash, validate_password_hash},
+    web::parameters::check_value,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Webhook {
     pub name: String,
-    pub command: String,
+    /// A command that's executed by the shell.
+    #[serde(default = "Default::default")]
+    pub command: Option<String>,
+    /// A program that's executed with `args`. Alternative to `command`.
+    #[serde(default = "Default::default")]
+    pub program: Option<String>,
+    /// The arguments for `program`. Each argument is rendered on its own.
+    #[serde(default = "Default::default")]
+    pub args: Vec<String>,
     pub cwd: PathBuf,
     #[serde(default = "default_pueue_group")]
     pub pueue_group: String,
+    #[serde(default = "Default::default")]
+    pub mode: WebhookMode,
+    /// How many seconds to wait for a task to finish in sync mode.
+    #[serde(default = "default_sync_timeout")]
+    pub sync_timeout: u64,
+    /// Environment variables of the server that're passed on to the task.
+    #[serde(default = "default_inherit_env")]
+    pub inherit_env: Vec<String>,
+    /// Pass the server's whole environment to the task.
+    /// This was the default behavior in earlier versions.
+    #[serde(default = "Default::default")]
+    pub inherit_all_env: bool,
+    /// Static environment variables for the task.
+    #[serde(default = "Default::default")]
+    pub env: HashMap<String, String>,
+    /// Environment variables that're rendered with the request's parameters.
+    #[serde(default = "Default::default")]
+    pub parameter_env: HashMap<String, String>,
+    /// Shell-quote every parameter that's rendered into the command.
+    #[serde(default = "default_true")]
+    pub escape_parameters: bool,
+    /// Allow the `{{raw name}}` helper, which renders a parameter without quoting.
+    #[serde(default = "Default::default")]
+    pub allow_raw_parameters: bool,
+    /// The parameters this webhook accepts.
+    /// If this is empty, any parameters are accepted.
+    #[serde(default = "Default::default")]
+    pub parameters: HashMap<String, Parameter>,
+    /// Parameters that're extracted from the request's json body via JSON Pointers.
+    /// E.g. `sha: /head_commit/id`.
+    #[serde(default = "Default::default")]
+    pub parameter_mapping: HashMap<String, String>,
+    /// Requests are only turned into tasks, if all filters match.
+    #[serde(default = "Default::default")]
+    pub filters: Vec<Filter>,
+    /// A shared token that's sent in the `token_header`, e.g. by Gitlab.
+    #[serde(default = "Default::default")]
+    pub token: Option<String>,
+    /// A file that contains the `token`.
+    #[serde(default = "Default::default")]
+    pub token_file: Option<String>,
+    /// An environment variable that contains the `token`.
+    #[serde(default = "Default::default")]
+    pub token_env: Option<String>,
+    /// The header that contains the shared `token`.
+    #[serde(default = "default_token_header")]
+    pub token_header: String,
+    /// Overrides the global `secret` for this webhook.
+    #[serde(default = "Default::default")]
+    pub secret: Option<String>,
+    /// A file that contains the `secret`.
+    #[serde(default = "Default::default")]
+    pub secret_file: Option<String>,
+    /// An environment variable that contains the `secret`.
+    #[serde(default = "Default::default")]
+    pub secret_env: Option<String>,
+    /// Overrides the global `secrets` for this webhook.
+    #[serde(default = "Default::default")]
+    pub secrets: Option<Vec<NamedSecret>>,
+    /// Overrides the global `signature_provider` for this webhook.
+    #[serde(default = "Default::default")]
+    pub signature_provider: Option<SignatureProvider>,
+    /// Overrides the global `timestamped_signatures` for this webhook.
+    #[serde(default = "Default::default")]
+    pub timestamped_signatures: Option<bool>,
+    /// Overrides the global `basic_auth_user` for this webhook.
+    #[serde(default = "Default::default")]
+    pub basic_auth_user: Option<String>,
+    /// Overrides the global `basic_auth_password` for this webhook.
+    #[serde(default = "Default::default")]
+    pub basic_auth_password: Option<String>,
+    /// A file that contains the `basic_auth_password`.
+    #[serde(default = "Default::default")]
+    pub basic_auth_password_file: Option<String>,
+    /// An environment variable that contains the `basic_auth_password`.
+    #[serde(default = "Default::default")]
+    pub basic_auth_password_env: Option<String>,
+    /// Overrides the global `basic_auth_users` for this webhook.
+    #[serde(default = "Default::default")]
+    pub basic_auth_users: Option<Vec<BasicAuthUser>>,
+    /// Overrides the global `required_auth` for this webhook.
+    #[serde(default = "Default::default")]
+    pub required_auth: Option<Vec<AuthMethod>>,
+    /// Claims of a JWT that have to match glob patterns, in addition to the global `jwt.claims`.
+    #[serde(default = "Default::default")]
+    pub jwt_claims: HashMap<String, String>,
+    /// Overrides the global `allowed_client_certs` for this webhook.
+    #[serde(default = "Default::default")]
+    pub allowed_client_certs: Option<Vec<String>>,
+    /// Overrides the global `allowed_networks` for this webhook.
+    #[serde(default = "Default::default")]
+    pub allowed_networks: Option<Vec<IpNet>>,
+}
+
+/// A filter on a header or a payload field of a request.
+/// Exactly one of `header` and `field` and exactly one of `glob` and `regex` must be set.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Filter {
+    /// The name of the header that's checked, e.g. `X-GitHub-Event`.
+    #[serde(default = "Default::default")]
+    pub header: Option<String>,
+    /// A JSON Pointer to the payload field that's checked, e.g. `/ref`.
+    #[serde(default = "Default::default")]
+    pub field: Option<String>,
+    /// A glob pattern the whole value has to match.
+    #[serde(default = "Default::default")]
+    pub glob: Option<String>,
+    /// A regex the whole value has to match.
+    #[serde(default = "Default::default")]
+    pub regex: Option<String>,
+}
+
+impl Filter {
+    /// Check the filter for configuration errors.
+    pub fn validate(&self) -> Result<()> {
+        match (&self.header, &self.field) {
+            (Some(_), Some(_)) | (None, None) => {
+                bail!("Exactly one of header and field must be set in filter {self:?}")
+            }
+            _ => (),
+        }
+
+        match (&self.glob, &self.regex) {
+            (Some(glob), None) => {
+                Glob::new(glob).wrap_err(format!("Invalid glob in filter {self:?}"))?;
+            }
+            (None, Some(regex)) => {
+                Regex::new(regex).wrap_err(format!("Invalid regex in filter {self:?}"))?;
+            }
+            _ => bail!("Exactly one of glob and regex must be set in filter {self:?}"),
+        }
+
+        Ok(())
+    }
+}
+
+/// The declaration of a single webhook parameter.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Parameter {
+    /// Requests without this parameter are rejected.
+    #[serde(default = "Default::default")]
+    pub required: bool,
+    /// The value that's used, if the parameter isn't provided.
+    #[serde(default = "Default::default")]
+    pub default: Option<String>,
+    #[serde(rename = "type", default = "Default::default")]
+    pub parameter_type: ParameterType,
+    /// A regex the whole value has to match.
+    #[serde(default = "Default::default")]
+    pub pattern: Option<String>,
+    /// A list of allowed values.
+    #[serde(rename = "enum", default = "Default::default")]
+    pub allowed_values: Option<Vec<String>>,
+    #[serde(default = "Default::default")]
+    pub max_length: Option<usize>,
+}
+
+/// The type a parameter's value must have.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    #[default]
+    String,
+    /// A signed integer.
+    Int,
+    /// Either `true` or `false`.
+    Bool,
+    /// A file path without any `..` components.
+    Path,
+}
+
+impl Webhook {
+    /// Load the secrets that're configured via files or environment variables.
+    pub fn load_secrets(&mut self) -> Result<()> {
+        load_secret("token", &mut self.token, &self.token_file, &self.token_env)?;
+        load_secret(
+            "secret",
+            &mut self.secret,
+            &self.secret_file,
+            &self.secret_env,
+        )?;
+        load_secret(
+            "basic_auth_password",
+            &mut self.basic_auth_password,
+            &self.basic_auth_password_file,
+            &self.basic_auth_password_env,
+        )?;
+        for secret in self.secrets.iter_mut().flatten() {
+            secret.load()?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the webhook for configuration errors.
+    pub fn validate(&self) -> Result<()> {
+        match (&self.command, &self.program) {
+            (Some(_), Some(_)) => {
+                bail!(
+                    "Webhook {}: Only one of command and program may be specified",
+                    self.name
+                )
+            }
+            (None, None) => bail!(
+                "Webhook {}: Either command or program is required",
+                self.name
+            ),
+            (None, Some(_)) => (),
+            (Some(_), None) if !self.args.is_empty() => {
+                bail!("Webhook {}: args can only be used with program", self.name)
+            }
+            (Some(_), None) => (),
+        }
+
+        // Triple-stash expressions aren't escaped by handlebars and would bypass the quoting.
+        let command = self.command.as_deref().unwrap_or_default();
+        if self.escape_parameters && (command.contains("{{{") || command.contains("{{&")) {
+            bail!(
+                "Webhook {}: Unescaped expressions aren't allowed when escape_parameters is \
+                enabled. Use {{{{raw name}}}} together with allow_raw_parameters instead.",
+                self.name
+            );
+        }
+        if self.escape_parameters {
+            validate_shell_template(command)
+                .wrap_err(format!("Webhook {}: Invalid command template", self.name))?;
+        }
+
+        for (name, pointer) in self.parameter_mapping.iter() {
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                bail!(
+                    "Webhook {}: Mapping for parameter {name} must be a JSON Pointer starting \
+                    with '/'",
+                    self.name
+                );
+            }
+        }
+
+        for filter in self.filters.iter() {
+            filter
+                .validate()
+                .wrap_err(format!("Webhook {}: Invalid filter", self.name))?;
+        }
+
+        validate_claim_patterns(&self.jwt_claims)
+            .wrap_err(format!("Webhook {}: Invalid jwt_claims", self.name))?;
+
+        for (name, parameter) in self.parameters.iter() {
+            if let Some(pattern) = &parameter.pattern {
+                Regex::new(pattern).wrap_err(format!(
+                    "Webhook {}: Invalid pattern for parameter {name}",
+                    self.name
+                ))?;
+            }
+            // Defaults are inserted as they are, so they have to satisfy their own declaration.
+            if let Some(default) = &parameter.default
+                && let Err(message) = check_value(parameter, default)
+            {
+                bail!(
+                    "Webhook {}: Invalid default for parameter {name}: {message}",
+                    self.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that escaped parameters only end up where shell quoting protects them.
+///
+/// Parameters are single-quoted, which only works outside of any quotes and substitutions.
+/// Instead of following the whole shell syntax, parameters are refused inside of quotes and after
+/// any substitution, ANSI-C quote or here-document. Besides `{{name}}`, only the `{{raw name}}`
+/// helper is allowed, as the output of other helpers may not be quoted.
+fn validate_shell_template(command: &str) -> Result<()> {
+    let mut quote: Option<char> = None;
+    let mut substitution = false;
+    let mut rest = command;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(expression) = rest.strip_prefix("{{") {
+            let Some(end) = expression.find("}}") else {
+                bail!("Unclosed expression");
+            };
+            let inner = expression[..end].trim_matches('~').trim();
+
+            match inner.strip_prefix("raw ") {
+                Some(name) if !name.trim().contains(char::is_whitespace) => (),
+                Some(_) => bail!("{{{{{inner}}}}}: raw only takes a single parameter"),
+                None if inner.is_empty()
+                    || inner.contains(char::is_whitespace)
+                    || inner.starts_with(['#', '/', '^', '!', '>', '&', '{']) =>
+                {
+                    bail!(
+                        "{{{{{inner}}}}}: Only {{{{name}}}} and {{{{raw name}}}} expressions are \
+                        allowed when escape_parameters is enabled"
+                    );
+                }
+                None if quote.is_some() || substitution => bail!(
+                    "{{{{{inner}}}}}: Parameters can't be used inside of quotes, substitutions or \
+                    here-documents. They're quoted automatically, so remove the surrounding quotes."
+                ),
+                None => (),
+            }
+
+            rest = &expression[end + 2..];
+            continue;
+        }
+
+        let mut len = c.len_utf8();
+        let next = rest[len..].chars().next();
+        match (quote, c) {
+            // Escaped characters don't open or close quotes.
+            (None | Some('"'), '\\') => len += next.map_or(0, char::len_utf8),
+            (None | Some('"'), '`') => substitution = true,
+            (None | Some('"'), '$') if matches!(next, Some('(' | '{' | '\'' | '"')) => {
+                substitution = true;
+            }
+            (None, '<') if next == Some('<') => substitution = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(current), _) if current == c => quote = None,
+            _ => (),
+        }
+        rest = &rest[len..];
+    }
+
+    Ok(())
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Webhook {
+            name: String::new(),
+            command: None,
+            program: None,
+            args: Vec::new(),
+            cwd: PathBuf::new(),
+            pueue_group: default_pueue_group(),
+            mode: WebhookMode::default(),
+            sync_timeout: default_sync_timeout(),
+            inherit_env: default_inherit_env(),
+            inherit_all_env: false,
+            env: HashMap::new(),
+            parameter_env: HashMap::new(),
+            escape_parameters: true,
+            allow_raw_parameters: false,
+            parameters: HashMap::new(),
+            parameter_mapping: HashMap::new(),
+            filters: Vec::new(),
+            token: None,
+            token_file: None,
+            token_env: None,
+            token_header: default_token_header(),
+            secret: None,
+            secret_file: None,
+            secret_env: None,
+            secrets: None,
+            signature_provider: None,
+            timestamped_signatures: None,
+            basic_auth_user: None,
+            basic_auth_password: None,
+            basic_auth_password_file: None,
+            basic_auth_password_env: None,
+            basic_auth_users: None,
+            required_auth: None,
+            jwt_claims: HashMap::new(),
+            allowed_client_certs: None,
+            allowed_networks: None,
+        }
+    }
 }
 
 fn default_pueue_group() -> String {
     "webhook".to_string()
 }
 
+fn default_token_header() -> String {
+    "X-Gitlab-Token".to_string()
+}
+
+fn default_timestamp_header() -> String {
+    "X-Webhook-Timestamp".to_string()
+}
+
+fn default_timestamp_tolerance() -> u64 {
+    300
+}
+
+fn default_delivery_id_cache_size() -> usize {
+    10000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_sync_timeout() -> u64 {
+    60
+}
+
+fn default_inherit_env() -> Vec<String> {
+    ["PATH", "HOME", "USER", "LANG"]
+        .into_iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Determines whether a webhook request returns immediately or waits for the task to finish.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookMode {
+    /// Respond as soon as the task has been added to Pueue.
+    #[default]
+    Async,
+    /// Wait for the task to finish and respond with its output.
+    Sync,
+}
+
+/// Configuration of a Pueue group that's used by webhooks.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct PueueGroup {
+    /// The amount of tasks that may run in parallel in this group.
+    pub parallel_tasks: usize,
+}
+
+/// A basic auth user with a hashed password.
 #[derive(Debug, Deserialize, Clone)]
+pub struct BasicAuthUser {
+    pub name: String,
+    /// An argon2 or bcrypt hash of the password.
+    /// Can be generated with `webhookserver hash-password`.
+    pub password_hash: String,
+    /// The webhooks this user may trigger. The user may trigger all webhooks, if this isn't set.
+    #[serde(default = "Default::default")]
+    pub webhooks: Option<Vec<String>>,
+    /// Whether the user may read the status and output of tasks via the `/tasks` routes.
+    #[serde(default = "Default::default")]
+    pub read_tasks: bool,
+}
+
+impl BasicAuthUser {
+    /// Check whether this user may trigger a webhook.
+    pub fn may_trigger(&self, webhook: &str) -> bool {
+        self.webhooks
+            .as_ref()
+            .is_none_or(|webhooks| webhooks.iter().any(|name| name == webhook))
+    }
+}
+
+/// An API token for `Authorization: Bearer` authentication.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiToken {
+    /// The name of the token, which is logged for every request it authenticates.
+    pub name: String,
+    /// The hex encoded sha256 hash of the token.
+    /// Can be generated with `webhookserver generate-token`.
+    pub token_hash: String,
+    /// The token is refused after this point in time, e.g. `2030-01-01T00:00:00Z`.
+    #[serde(default = "Default::default")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The webhooks this token may trigger. The token may trigger all webhooks, if this isn't set.
+    #[serde(default = "Default::default")]
+    pub webhooks: Option<Vec<String>>,
+    /// Whether the token may read the status and output of tasks via the `/tasks` routes.
+    #[serde(default = "Default::default")]
+    pub read_tasks: bool,
+}
+
+impl ApiToken {
+    /// Check whether this token may trigger a webhook.
+    pub fn may_trigger(&self, webhook: &str) -> bool {
+        self.webhooks
+            .as_ref()
+            .is_none_or(|webhooks| webhooks.iter().any(|name| name == webhook))
+    }
+
+    /// Check whether the token is expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+/// Verification of JWTs, e.g. the OIDC tokens of Github Actions.
+#[derive(Debug, Deserialize, Clone)]
+pub struct JwtSettings {
+    /// A file with the JSON Web Key Set that's used to verify the signatures.
+    pub jwks_file: PathBuf,
+    /// The expected `iss` claim.
+    pub issuer: String,
+    /// The expected `aud` claim.
+    pub audience: String,
+    /// Claims that have to match glob patterns for every request, e.g. `repository_owner: my-org`.
+    #[serde(default = "Default::default")]
+    pub claims: HashMap<String, String>,
+    /// The allowed clock skew in seconds, when checking `exp` and `nbf`.
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway: u64,
+    /// Whether JWTs may read the status and output of tasks via the `/tasks` routes.
+    #[serde(default = "Default::default")]
+    pub read_tasks: bool,
+    /// The keys of `jwks_file`, which are loaded on startup.
+    #[serde(skip)]
+    pub keys: Vec<Jwk>,
+}
+
+impl JwtSettings {
+    /// Load the keys from the `jwks_file` and check the configuration.
+    fn load(&mut self) -> Result<()> {
+        if self.issuer.is_empty() || self.audience.is_empty() {
+            bail!("jwt: issuer and audience must not be empty");
+        }
+        validate_claim_patterns(&self.claims).wrap_err("jwt: Invalid claims")?;
+
+        let file = File::open(&self.jwks_file)
+            .wrap_err(format!("jwt: Failed to open {:?}", self.jwks_file))?;
+        let jwks: JwkSet = serde_json::from_reader(BufReader::new(file))
+            .wrap_err(format!("jwt: Failed to parse JWKS {:?}", self.jwks_file))?;
+        if jwks.keys.is_empty() {
+            bail!("jwt: {:?} doesn't contain any keys", self.jwks_file);
+        }
+        self.keys = jwks.keys;
+
+        Ok(())
+    }
+}
+
+fn default_jwt_leeway() -> u64 {
+    60
+}
+
+/// Check that all claim patterns are valid globs.
+fn validate_claim_patterns(claims: &HashMap<String, String>) -> Result<()> {
+    for (claim, pattern) in claims.iter() {
+        Glob::new(pattern).wrap_err(format!("Invalid glob for claim {claim}"))?;
+    }
+
+    Ok(())
+}
+
+/// The methods a request can be authenticated with.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// A HMAC signature of the payload.
+    Signature,
+    BasicAuth,
+    /// A shared token in a header.
+    Token,
+    /// An API token in the `Authorization: Bearer` header.
+    Bearer,
+    /// A JWT in the `Authorization: Bearer` header.
+    Jwt,
+    /// A TLS client certificate that's signed by the `client_ca`.
+    ClientCert,
+}
+
+/// The authentication settings that apply to a request.
+/// The settings of the targeted webhook take precedence over the global settings.
+#[derive(Debug, Clone)]
+pub struct AuthSettings {
+    /// All secrets that're accepted for signatures, including expired ones.
+    pub secrets: Vec<NamedSecret>,
+    pub signature_provider: SignatureProvider,
+    /// Whether the signature covers the timestamp and the body.
+    pub timestamped_signatures: bool,
+    pub timestamp_header: String,
+    pub timestamp_tolerance: u64,
+    pub basic_auth_user: Option<String>,
+    pub basic_auth_password: Option<String>,
+    pub basic_auth_users: Vec<BasicAuthUser>,
+    pub api_tokens: Vec<ApiToken>,
+    pub jwt: Option<JwtSettings>,
+    /// The targeted webhook's claim patterns. The global patterns are part of `jwt`.
+    pub jwt_claims: HashMap<String, String>,
+    pub token: Option<String>,
+    pub token_header: String,
+    /// Whether client certificates are verified.
+    pub client_cert: bool,
+    /// The client certificates that are accepted. Client certificates are only a configured
+    /// method, if this isn't empty.
+    pub allowed_client_certs: Vec<String>,
+    /// All of these methods must succeed.
+    /// If this is empty, a single successful method out of the configured ones is sufficient.
+    pub required_methods: Vec<AuthMethod>,
+}
+
+impl AuthSettings {
+    /// All methods that're configured, i.e. that have the necessary credentials.
+    pub fn configured_methods(&self) -> Vec<AuthMethod> {
+        let mut methods = Vec::new();
+        if !self.secrets.is_empty() {
+            methods.push(AuthMethod::Signature);
+        }
+        if (self.basic_auth_user.is_some() && self.basic_auth_password.is_some())
+            || !self.basic_auth_users.is_empty()
+        {
+            methods.push(AuthMethod::BasicAuth);
+        }
+        if self.token.is_some() {
+            methods.push(AuthMethod::Token);
+        }
+        if !self.api_tokens.is_empty() {
+            methods.push(AuthMethod::Bearer);
+        }
+        if self.jwt.is_some() {
+            methods.push(AuthMethod::Jwt);
+        }
+        if self.client_cert && !self.allowed_client_certs.is_empty() {
+            methods.push(AuthMethod::ClientCert);
+        }
+
+        methods
+    }
+
+    /// Check that all required methods have their credentials.
+    pub fn validate(&self) -> Result<()> {
+        let configured = self.configured_methods();
+        for method in self.required_methods.iter() {
+            if !configured.contains(method) {
+                bail!("{method:?} authentication is required, but not configured");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// One of multiple secrets that're accepted for signatures, which allows to rotate secrets.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NamedSecret {
+    /// The name of the secret, which is logged when it's used. The secret itself is never logged.
+    pub name: String,
+    #[serde(default = "Default::default")]
+    pub secret: String,
+    /// A file that contains the `secret`.
+    #[serde(default = "Default::default")]
+    pub secret_file: Option<String>,
+    /// An environment variable that contains the `secret`.
+    #[serde(default = "Default::default")]
+    pub secret_env: Option<String>,
+    /// The secret is refused after this point in time, e.g. `2030-01-01T00:00:00Z`.
+    #[serde(default = "Default::default")]
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+impl NamedSecret {
+    /// Load the secret, if it's configured via a file or an environment variable.
+    fn load(&mut self) -> Result<()> {
+        let mut secret = Some(self.secret.clone()).filter(|secret| !secret.is_empty());
+        load_secret("secret", &mut secret, &self.secret_file, &self.secret_env)
+            .wrap_err(format!("Secret {}", self.name))?;
+        self.secret = secret.unwrap_or_default();
+
+        Ok(())
+    }
+
+    /// Check whether the secret is expired.
+    pub fn is_expired(&self) -> bool {
+        self.not_after
+            .is_some_and(|not_after| not_after <= Utc::now())
+    }
+}
+
+/// Check that secrets have unique names and aren't empty.
+fn validate_secrets(secrets: &[NamedSecret]) -> Result<()> {
+    for (index, secret) in secrets.iter().enumerate() {
+        if secret.name.is_empty() {
+            bail!("Secret {index}: A name is required");
+        }
+        if secret.secret.is_empty() {
+            bail!("Secret {}: The secret must not be empty", secret.name);
+        }
+        if secrets[..index]
+            .iter()
+            .any(|other| other.name == secret.name)
+        {
+            bail!("Secret {}: Names must be unique", secret.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// The services whose signature schemes are supported.
+/// Each provider has its own headers and signs its own representation of the request.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureProvider {
+    /// `Signature`, `X-Hub-Signature-256` or `X-Hub-Signature` headers over the body.
+    #[default]
+    Github,
+    /// `X-Slack-Signature` over `v0:{timestamp}:{body}`.
+    Slack,
+    /// `Stripe-Signature` over `{timestamp}.{body}`.
+    Stripe,
+}
+
+/// The HMAC algorithms that can be used to sign a payload.
+/// The variants are ordered by strength, which allows to refuse weaker algorithms.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl SignatureAlgorithm {
+    /// The prefix that's used in signature headers, e.g. `sha256=`
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            SignatureAlgorithm::Sha1 => "sha1=",
+            SignatureAlgorithm::Sha256 => "sha256=",
+            SignatureAlgorithm::Sha512 => "sha512=",
+        }
+    }
+
+    /// Determine the algorithm by the prefix of a signature header's value.
+    pub fn from_header_value(value: &str) -> Option<Self> {
+        [
+            SignatureAlgorithm::Sha1,
+            SignatureAlgorithm::Sha256,
+            SignatureAlgorithm::Sha512,
+        ]
+        .into_iter()
+        .find(|algorithm| value.starts_with(algorithm.prefix()))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct Settings {
     pub domain: String,
     pub port: i32,
     #[serde(default = "Default::default")]
     pub secret: Option<String>,
+    /// A file that contains the `secret`, e.g. a Docker secret in `/run/secrets/`.
+    #[serde(default = "Default::default")]
+    pub secret_file: Option<String>,
+    /// An environment variable that contains the `secret`.
+    #[serde(default = "Default::default")]
+    pub secret_env: Option<String>,
+    /// Multiple named secrets, which are accepted in addition to `secret`.
+    #[serde(default = "Default::default")]
+    pub secrets: Vec<NamedSecret>,
     #[serde(default = "Default::default")]
     pub ssl_private_key: Option<String>,
     #[serde(default = "Default::default")]
     pub ssl_cert_chain: Option<String>,
+    /// Path to the CA certificates that sign client certificates. Enables client certificates.
+    #[serde(default = "Default::default")]
+    pub client_ca: Option<String>,
+    /// Refuse TLS connections without a valid client certificate.
+    #[serde(default = "Default::default")]
+    pub client_cert_required: bool,
+    /// Subjects, common names or alternative names of the client certificates that authenticate
+    /// requests. Client certificates don't authenticate anything, if this is empty.
+    #[serde(default = "Default::default")]
+    pub allowed_client_certs: Vec<String>,
+    /// Only clients from these networks may send requests. All clients are allowed, if it's empty.
+    #[serde(default = "Default::default")]
+    pub allowed_networks: Vec<IpNet>,
+    /// Proxies whose `X-Forwarded-For` header is used to determine the client's IP.
+    #[serde(default = "Default::default")]
+    pub trusted_proxies: Vec<IpNet>,
     #[serde(default = "Default::default")]
     pub basic_auth_user: Option<String>,
     #[serde(default = "Default::default")]
     pub basic_auth_password: Option<String>,
+    /// A file that contains the `basic_auth_password`.
+    #[serde(default = "Default::default")]
+    pub basic_auth_password_file: Option<String>,
+    /// An environment variable that contains the `basic_auth_password`.
+    #[serde(default = "Default::default")]
+    pub basic_auth_password_env: Option<String>,
+    /// Basic auth users with hashed passwords.
+    #[serde(default = "Default::default")]
+    pub basic_auth_users: Vec<BasicAuthUser>,
     #[serde(default = "Default::default")]
     pub basic_auth_and_secret: bool,
+    /// API tokens for `Authorization: Bearer` authentication.
+    #[serde(default = "Default::default")]
+    pub api_tokens: Vec<ApiToken>,
+    /// Verification of JWTs in the `Authorization: Bearer` header.
+    #[serde(default = "Default::default")]
+    pub jwt: Option<JwtSettings>,
+    /// All of these authentication methods must succeed.
+    #[serde(default = "Default::default")]
+    pub required_auth: Vec<AuthMethod>,
+    #[serde(default = "Default::default")]
+    pub min_signature_algorithm: SignatureAlgorithm,
+    /// The scheme that's used to sign requests.
+    #[serde(default = "Default::default")]
+    pub signature_provider: SignatureProvider,
+    /// Signatures cover `{timestamp}.{body}` instead of only the body.
+    #[serde(default = "Default::default")]
+    pub timestamped_signatures: bool,
+    /// The header that contains the unix timestamp of timestamped signatures.
+    #[serde(default = "default_timestamp_header")]
+    pub timestamp_header: String,
+    /// How many seconds a timestamp may differ from the server's time.
+    #[serde(default = "default_timestamp_tolerance")]
+    pub timestamp_tolerance: u64,
+    /// Headers that contain a unique id of a request, e.g. `X-GitHub-Delivery`.
+    /// Requests with an id that has already been seen are refused.
+    #[serde(default = "Default::default")]
+    pub delivery_id_headers: Vec<String>,
+    /// How many delivery ids are remembered.
+    #[serde(default = "default_delivery_id_cache_size")]
+    pub delivery_id_cache_size: usize,
     #[serde(default = "Default::default")]
     pub webhooks: Vec<Webhook>,
+    #[serde(default = "Default::default")]
+    pub groups: HashMap<String, PueueGroup>,
 }
 
 impl Settings {
     pub fn new() -> Result<Self> {
         info!("Init settings file");
-        let settings = parse_config()?;
+        let mut settings = parse_config()?;
+        settings.load_secrets()?;
 
         if settings.basic_auth_password.is_some() || settings.basic_auth_user.is_some() {
             settings
@@ -60,23 +871,266 @@ impl Settings {
 
         // Verify that everything is in place, if `basic_auth_and_secret` is activated
         if settings.basic_auth_and_secret {
+            if settings.secrets.is_empty() {
+                settings
+                    .secret
+                    .as_ref()
+                    .ok_or_else(|| eyre!("Can't find secret in config"))?;
+            }
+            if settings.basic_auth_users.is_empty() {
+                settings
+                    .basic_auth_user
+                    .as_ref()
+                    .ok_or_else(|| eyre!("Can't find basic_auth_user in config"))?;
+                settings
+                    .basic_auth_password
+                    .as_ref()
+                    .ok_or_else(|| eyre!("Can't find basic_auth_password in config"))?;
+            }
+        }
+
+        validate_secrets(&settings.secrets)?;
+        settings.validate_basic_auth_users(&settings.basic_auth_users)?;
+        settings.validate_api_tokens()?;
+        if let Some(jwt) = settings.jwt.as_mut() {
+            jwt.load()?;
+        }
+
+        if settings.client_ca.is_some()
+            && (settings.ssl_cert_chain.is_none() || settings.ssl_private_key.is_none())
+        {
+            bail!("client_ca can only be used together with ssl_cert_chain and ssl_private_key");
+        }
+        if settings.client_cert_required && settings.client_ca.is_none() {
+            bail!("client_cert_required needs a client_ca");
+        }
+        let has_allowed_client_certs = !settings.allowed_client_certs.is_empty()
+            || settings
+                .webhooks
+                .iter()
+                .any(|webhook| webhook.allowed_client_certs.is_some());
+        if has_allowed_client_certs && settings.client_ca.is_none() {
+            bail!("allowed_client_certs needs a client_ca");
+        }
+
+        settings.get_auth_settings(None).validate()?;
+
+        for webhook in settings.webhooks.iter() {
+            webhook.validate()?;
+
+            if let Some(secrets) = &webhook.secrets {
+                validate_secrets(secrets)
+                    .wrap_err(format!("Webhook {}: Invalid secrets", webhook.name))?;
+            }
+
+            if webhook.basic_auth_user.is_some() != webhook.basic_auth_password.is_some() {
+                bail!(
+                    "Webhook {}: basic_auth_user and basic_auth_password must be set together",
+                    webhook.name
+                );
+            }
+
+            if let Some(users) = &webhook.basic_auth_users {
+                settings.validate_basic_auth_users(users).wrap_err(format!(
+                    "Webhook {}: Invalid basic_auth_users",
+                    webhook.name
+                ))?;
+            }
+
             settings
-                .secret
-                .as_ref()
-                .ok_or_else(|| eyre!("Can't find secret in config"))?;
-            settings
-                .basic_auth_user
-                .as_ref()
-                .ok_or_else(|| eyre!("Can't find basic_auth_user in config"))?;
-            settings
-                .basic_auth_password
-                .as_ref()
-                .ok_or_else(|| eyre!("Can't find basic_auth_password in config"))?;
+                .get_auth_settings(Some(webhook))
+                .validate()
+                .wrap_err(format!("Webhook {}: Invalid authentication", webhook.name))?;
         }
 
         Ok(settings)
     }
 
+    /// Load the secrets that're configured via files or environment variables.
+    fn load_secrets(&mut self) -> Result<()> {
+        load_secret(
+            "secret",
+            &mut self.secret,
+            &self.secret_file,
+            &self.secret_env,
+        )?;
+        load_secret(
+            "basic_auth_password",
+            &mut self.basic_auth_password,
+            &self.basic_auth_password_file,
+            &self.basic_auth_password_env,
+        )?;
+        for secret in self.secrets.iter_mut() {
+            secret.load()?;
+        }
+
+        for webhook in self.webhooks.iter_mut() {
+            webhook
+                .load_secrets()
+                .wrap_err(format!("Webhook {}", webhook.name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the password hashes and webhook allowlists of basic auth users.
+    fn validate_basic_auth_users(&self, users: &[BasicAuthUser]) -> Result<()> {
+        for user in users.iter() {
+            if user.name.is_empty() || user.name.contains(':') {
+                bail!(
+                    "Basic auth user {:?}: Names must be non-empty without colons",
+                    user.name
+                );
+            }
+            validate_password_hash(&user.password_hash)
+                .wrap_err(format!("Basic auth user {}", user.name))?;
+
+            for name in user.webhooks.iter().flatten() {
+                if !self.webhooks.iter().any(|webhook| &webhook.name == name) {
+                    bail!("Basic auth user {}: Unknown webhook {name}", user.name);
+                }
+            }
+        }
+
+        // Prepare the dummy hash for unknown users, so the first unknown user isn't slower.
+        if let Some(user) = users.first() {
+            get_dummy_hash(&user.password_hash);
+        }
+
+        Ok(())
+    }
+
+    /// Check the hashes, names and webhook scopes of all API tokens.
+    fn validate_api_tokens(&self) -> Result<()> {
+        for (index, token) in self.api_tokens.iter().enumerate() {
+            if token.name.is_empty() {
+                bail!("API token {index}: A name is required");
+            }
+            if self.api_tokens[..index]
+                .iter()
+                .any(|other| other.name == token.name)
+            {
+                bail!("API token {}: Names must be unique", token.name);
+            }
+            if token.token_hash.len() != 64 || hex::decode(&token.token_hash).is_err() {
+                bail!(
+                    "API token {}: token_hash must be a hex encoded sha256 hash",
+                    token.name
+                );
+            }
+
+            for name in token.webhooks.iter().flatten() {
+                if !self.webhooks.iter().any(|webhook| &webhook.name == name) {
+                    bail!("API token {}: Unknown webhook {name}", token.name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the authentication settings for a request.
+    /// `webhook` is the webhook that's targeted by the request, if there's any.
+    pub fn get_auth_settings(&self, webhook: Option<&Webhook>) -> AuthSettings {
+        // Empty values are treated as if they weren't set.
+        let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+
+        let global_required = if self.basic_auth_and_secret {
+            vec![AuthMethod::Signature, AuthMethod::BasicAuth]
+        } else {
+            self.required_auth.clone()
+        };
+
+        // The single `secret` is treated like a secret that's named `secret`.
+        let get_secrets = |secret: &Option<String>, secrets: &[NamedSecret]| {
+            let mut all_secrets: Vec<NamedSecret> = non_empty(secret)
+                .map(|secret| NamedSecret {
+                    name: "secret".to_string(),
+                    secret,
+                    secret_file: None,
+                    secret_env: None,
+                    not_after: None,
+                })
+                .into_iter()
+                .collect();
+            all_secrets.extend(secrets.iter().cloned());
+            all_secrets
+        };
+
+        let Some(webhook) = webhook else {
+            return AuthSettings {
+                secrets: get_secrets(&self.secret, &self.secrets),
+                signature_provider: self.signature_provider,
+                timestamped_signatures: self.timestamped_signatures,
+                timestamp_header: self.timestamp_header.clone(),
+                timestamp_tolerance: self.timestamp_tolerance,
+                basic_auth_user: non_empty(&self.basic_auth_user),
+                basic_auth_password: non_empty(&self.basic_auth_password),
+                basic_auth_users: self.basic_auth_users.clone(),
+                api_tokens: self.api_tokens.clone(),
+                jwt: self.jwt.clone(),
+                jwt_claims: HashMap::new(),
+                token: None,
+                token_header: default_token_header(),
+                client_cert: self.client_ca.is_some(),
+                allowed_client_certs: self.allowed_client_certs.clone(),
+                required_methods: global_required,
+            };
+        };
+
+        // Basic auth credentials are only overwritten as a whole.
+        let (basic_auth_user, basic_auth_password, basic_auth_users) =
+            if webhook.basic_auth_user.is_some() || webhook.basic_auth_users.is_some() {
+                (
+                    &webhook.basic_auth_user,
+                    &webhook.basic_auth_password,
+                    webhook.basic_auth_users.clone().unwrap_or_default(),
+                )
+            } else {
+                (
+                    &self.basic_auth_user,
+                    &self.basic_auth_password,
+                    self.basic_auth_users.clone(),
+                )
+            };
+
+        // Secrets are only overwritten as a whole.
+        let secrets = if non_empty(&webhook.secret).is_some() || webhook.secrets.is_some() {
+            get_secrets(
+                &webhook.secret,
+                webhook.secrets.as_deref().unwrap_or_default(),
+            )
+        } else {
+            get_secrets(&self.secret, &self.secrets)
+        };
+
+        AuthSettings {
+            secrets,
+            signature_provider: webhook
+                .signature_provider
+                .unwrap_or(self.signature_provider),
+            timestamped_signatures: webhook
+                .timestamped_signatures
+                .unwrap_or(self.timestamped_signatures),
+            timestamp_header: self.timestamp_header.clone(),
+            timestamp_tolerance: self.timestamp_tolerance,
+            basic_auth_user: non_empty(basic_auth_user),
+            basic_auth_password: non_empty(basic_auth_password),
+            basic_auth_users,
+            api_tokens: self.api_tokens.clone(),
+            jwt: self.jwt.clone(),
+            jwt_claims: webhook.jwt_claims.clone(),
+            token: non_empty(&webhook.token),
+            token_header: webhook.token_header.clone(),
+            client_cert: self.client_ca.is_some(),
+            allowed_client_certs: webhook
+                .allowed_client_certs
+                .clone()
+                .unwrap_or_else(|| self.allowed_client_certs.clone()),
+            required_methods: webhook.required_auth.clone().unwrap_or(global_required),
+        }
+    }
+
     /// Get settings for a specific webhook
     pub fn get_webhook_by_name(&self, name: &str) -> Result<Webhook, Error> {
         for webhook in self.webhooks.iter() {
@@ -91,6 +1145,45 @@ impl Settings {
     }
 }
 
+/// Load a secret from the file in `{name}_file` or the environment variable in `{name}_env`.
+/// At most one of the three forms may be used. A single trailing newline is removed from files.
+fn load_secret(
+    name: &str,
+    value: &mut Option<String>,
+    file: &Option<String>,
+    env: &Option<String>,
+) -> Result<()> {
+    match (value.is_some(), file, env) {
+        (_, None, None) => Ok(()),
+        (false, Some(path), None) => {
+            let content = std::fs::read_to_string(path)
+                .wrap_err(format!("Failed to read {name}_file {path:?}"))?;
+            let content = content
+                .strip_suffix('\n')
+                .map(|content| content.strip_suffix('\r').unwrap_or(content))
+                .unwrap_or(&content);
+            if content.is_empty() {
+                bail!("The {name}_file {path:?} is empty");
+            }
+
+            *value = Some(content.to_string());
+            Ok(())
+        }
+        (false, None, Some(variable)) => {
+            let content = std::env::var(variable).map_err(|error| {
+                eyre!("Failed to read {name}_env, environment variable {variable:?}: {error}")
+            })?;
+            if content.is_empty() {
+                bail!("The environment variable {variable:?} of {name}_env is empty");
+            }
+
+            *value = Some(content);
+            Ok(())
+        }
+        _ => bail!("Only one of {name}, {name}_file and {name}_env may be set"),
+    }
+}
+
 fn parse_config() -> Result<Settings> {
     info!("Parsing config files");
     let config_paths = get_config_paths()?;
@@ -142,3 +1235,65 @@ fn get_config_paths() -> Result<Vec<PathBuf>> {
 
     Ok(paths)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Secrets are read from files without their trailing newline
+    fn test_load_secret_file() {
+        let path = std::env::temp_dir().join(format!("webhook_secret_{}", std::process::id()));
+        std::fs::write(&path, "A secret string\n").unwrap();
+        let file = Some(path.to_string_lossy().to_string());
+
+        let mut secret = None;
+        load_secret("secret", &mut secret, &file, &None).unwrap();
+        assert_eq!(secret.as_deref(), Some("A secret string"));
+
+        // Inline secrets can't be combined with files
+        assert!(load_secret("secret", &mut secret, &file, &None).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        let mut secret = None;
+        let error = load_secret("secret", &mut secret, &file, &None).unwrap_err();
+        assert!(error.to_string().contains("Failed to read secret_file"));
+    }
+
+    #[test]
+    /// Named secrets can be read from files or environment variables as well
+    fn test_load_named_secret() {
+        let mut secret = NamedSecret {
+            name: "rotated".to_string(),
+            secret: String::new(),
+            secret_file: None,
+            secret_env: Some("CARGO_PKG_NAME".to_string()),
+            not_after: None,
+        };
+        secret.load().unwrap();
+        assert_eq!(secret.secret, env!("CARGO_PKG_NAME"));
+
+        // The secret is now set inline as well
+        assert!(secret.load().is_err());
+
+        secret.secret = String::new();
+        secret.secret_env = Some("WEBHOOK_SERVER_MISSING_SECRET".to_string());
+        let error = secret.load().unwrap_err();
+        assert!(format!("{error:?}").contains("Secret rotated"));
+    }
+
+    #[test]
+    /// Secrets are read from environment variables, which have to be set
+    fn test_load_secret_env() {
+        // Cargo sets this variable for tests.
+        let mut secret = None;
+        let variable = Some("CARGO_PKG_NAME".to_string());
+        load_secret("secret", &mut secret, &None, &variable).unwrap();
+        assert_eq!(secret.as_deref(), Some(env!("CARGO_PKG_NAME")));
+
+        let mut secret = None;
+        let variable = Some("WEBHOOK_SERVER_MISSING_SECRET".to_string());
+        assert!(load_secret("secret", &mut secret, &None, &variable).is_err());
+        assert!(secret.is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use std::{any::Any, path::PathBuf, sync::Arc};
+
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream};
+use rustls::{
+    RootCertStore,
+    server::{WebPkiClientVerifier, danger::ClientCertVerifier},
+};
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+use crate::{internal_prelude::*, settings::Settings, web::load_certs};
+
+/// The identity of a client, which has been verified via its TLS client certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// The whole subject of the certificate, e.g. `CN=deploy.example.com, O=Example`.
+    pub subject: String,
+    /// The common names of the subject.
+    pub common_names: Vec<String>,
+    /// The DNS names, email addresses and URIs of the subject alternative names.
+    pub alt_names: Vec<String>,
+}
+
+impl ClientIdentity {
+    /// Extract the identity from a DER encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let (_, certificate) = parse_x509_certificate(der)
+            .map_err(|error| eyre!("Failed to parse client certificate: {error}"))?;
+
+        let common_names = certificate
+            .subject()
+            .iter_common_name()
+            .filter_map(|name| name.as_str().ok())
+            .map(ToString::to_string)
+            .collect();
+
+        let mut alt_names = Vec::new();
+        if let Ok(Some(extension)) = certificate.subject_alternative_name() {
+            for name in extension.value.general_names.iter() {
+                match name {
+                    GeneralName::DNSName(name)
+                    | GeneralName::RFC822Name(name)
+                    | GeneralName::URI(name) => alt_names.push(name.to_string()),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(ClientIdentity {
+            subject: certificate.subject().to_string(),
+            common_names,
+            alt_names,
+        })
+    }
+
+    /// Check whether the subject, a common name or an alternative name is in the allowlist.
+    pub fn is_allowed(&self, allowlist: &[String]) -> bool {
+        allowlist.iter().any(|allowed| {
+            *allowed == self.subject
+                || self.common_names.contains(allowed)
+                || self.alt_names.contains(allowed)
+        })
+    }
+
+    /// A short name of the client for logging.
+    pub fn name(&self) -> &str {
+        self.common_names.first().unwrap_or(&self.subject)
+    }
+}
+
+/// Build the verifier for client certificates, which are signed by the `client_ca`.
+pub fn get_client_cert_verifier(
+    settings: &Settings,
+) -> Result<Option<Arc<dyn ClientCertVerifier>>> {
+    let Some(client_ca) = &settings.client_ca else {
+        return Ok(None);
+    };
+
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certs(PathBuf::from(client_ca))? {
+        roots
+            .add(certificate)
+            .wrap_err(format!("Invalid certificate in client_ca {client_ca:?}"))?;
+    }
+
+    let mut builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    if !settings.client_cert_required {
+        builder = builder.allow_unauthenticated();
+    }
+
+    let verifier = builder
+        .build()
+        .wrap_err("Failed to build client certificate verifier")?;
+
+    Ok(Some(verifier))
+}
+
+/// Store the identity of a verified client certificate in the connection's data.
+/// The certificate has already been verified by rustls at this point.
+pub fn store_client_identity(connection: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let (_, session) = stream.get_ref();
+    let Some(certificate) = session
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+    else {
+        return;
+    };
+
+    match ClientIdentity::from_der(certificate) {
+        Ok(identity) => {
+            data.insert(identity);
+        }
+        Err(error) => warn!("{error:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::pki_types::CertificateDer;
+    use rustls_pemfile::certs;
+
+    use super::*;
+
+    const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIICATCCAaegAwIBAgIUBbaKl4o+vCVsO1oMHE3iaoCNKCkwCgYIKoZIzj0EAwIw
+LzEbMBkGA1UEAwwSZGVwbG95LmV4YW1wbGUuY29tMRAwDgYDVQQKDAdFeGFtcGxl
+MCAXDTI2MTAxNjIzMDU0NVoYDzIxMjYwOTIyMjMwNTQ1WjAvMRswGQYDVQQDDBJk
+ZXBsb3kuZXhhbXBsZS5jb20xEDAOBgNVBAoMB0V4YW1wbGUwWTATBgcqhkjOPQIB
+BggqhkjOPQMBBwNCAATos7sWPPvYaYNy3Zr8vN/s/I85gnPwiUbPqatnUyyB4iqn
+xCzabL4LGtluhRb+74viWOeQy2oMCVfZakoke/9do4GeMIGbMB0GA1UdDgQWBBSG
+XdUeFISTVKuGtMg0XyDKIwojTDAfBgNVHSMEGDAWgBSGXdUeFISTVKuGtMg0XyDK
+IwojTDAPBgNVHRMBAf8EBTADAQH/MEgGA1UdEQRBMD+CD2RlcGxveS5pbnRlcm5h
+bIEPb3BzQGV4YW1wbGUuY29thhtzcGlmZmU6Ly9leGFtcGxlLmNvbS9kZXBsb3kw
+CgYIKoZIzj0EAwIDSAAwRQIgWXZ4pggMDJ+TneRrjqiDJyqP28xuXccdCc9f30Oh
+L/QCIQDplCgz/ggs2X5VMpNrh2R3/66zTWP8G0yI6OYbqA34yw==
+-----END CERTIFICATE-----";
+
+    fn certificate() -> CertificateDer<'static> {
+        certs(&mut CERTIFICATE.as_bytes()).next().unwrap().unwrap()
+    }
+
+    #[test]
+    /// The subject and alternative names are extracted from certificates
+    fn test_client_identity() {
+        let identity = ClientIdentity::from_der(&certificate()).unwrap();
+
+        assert_eq!(identity.subject, "CN=deploy.example.com, O=Example");
+        assert_eq!(identity.common_names, vec!["deploy.example.com"]);
+        assert_eq!(
+            identity.alt_names,
+            vec![
+                "deploy.internal",
+                "ops@example.com",
+                "spiffe://example.com/deploy"
+            ]
+        );
+        assert_eq!(identity.name(), "deploy.example.com");
+    }
+
+    #[test]
+    /// Clients are allowed by their subject, common name or alternative names
+    fn test_client_identity_allowlist() {
+        let identity = ClientIdentity::from_der(&certificate()).unwrap();
+
+        for allowed in [
+            "CN=deploy.example.com, O=Example",
+            "deploy.example.com",
+            "spiffe://example.com/deploy",
+        ] {
+            assert!(identity.is_allowed(&[allowed.to_string()]));
+        }
+
+        assert!(!identity.is_allowed(&[]));
+        assert!(!identity.is_allowed(&["example.com".to_string()]));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use globset::Glob;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::{
+    internal_prelude::*,
+    settings::{Filter, Webhook},
+    web::Payload,
+};
+
+/// Check whether a request is a Github `ping` event, which is sent when a webhook is created.
+pub fn is_github_ping(headers: &HashMap<String, String>) -> bool {
+    headers.get("x-github-event").map(String::as_str) == Some("ping")
+}
+
+/// Check all filters of a webhook against a request.
+/// Returns the reason, if the request should be skipped.
+pub fn check_filters(
+    webhook: &Webhook,
+    headers: &HashMap<String, String>,
+    payload: &Payload,
+) -> Option<String> {
+    for filter in webhook.filters.iter() {
+        let (source, value) = match (&filter.header, &filter.field) {
+            (Some(header), _) => (
+                format!("header {header}"),
+                headers.get(&header.to_lowercase()).cloned(),
+            ),
+            (None, Some(field)) => (
+                format!("field {field}"),
+                payload.body.pointer(field).and_then(scalar_to_string),
+            ),
+            (None, None) => continue,
+        };
+
+        let Some(value) = value else {
+            return Some(format!("{source} is missing"));
+        };
+
+        if !filter_matches(filter, &value) {
+            return Some(format!("{source} doesn't match: {value}"));
+        }
+    }
+
+    None
+}
+
+/// Check whether a single value matches the glob or regex of a filter.
+fn filter_matches(filter: &Filter, value: &str) -> bool {
+    if let Some(glob) = &filter.glob {
+        return match Glob::new(glob) {
+            Ok(glob) => glob.compile_matcher().is_match(value),
+            Err(error) => {
+                warn!("Invalid glob in filter {filter:?}: {error}");
+                false
+            }
+        };
+    }
+
+    if let Some(regex) = &filter.regex {
+        // The regex has to match the whole value.
+        return match Regex::new(&format!("^(?:{regex})$")) {
+            Ok(regex) => regex.is_match(value),
+            Err(error) => {
+                warn!("Invalid regex in filter {filter:?}: {error}");
+                false
+            }
+        };
+    }
+
+    false
+}
+
+/// Convert strings, numbers and booleans into strings, so they can be matched.
+pub fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Bool(value) => Some(value.to_string()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_webhook() -> Webhook {
+        Webhook {
+            filters: vec![
+                Filter {
+                    header: Some("X-GitHub-Event".to_string()),
+                    glob: Some("push".to_string()),
+                    ..Default::default()
+                },
+                Filter {
+                    field: Some("/ref".to_string()),
+                    regex: Some("refs/heads/(main|release/.+)".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn request(event: &str, git_ref: &str) -> (HashMap<String, String>, Payload) {
+        let headers = HashMap::from([("x-github-event".to_string(), event.to_string())]);
+        let payload = Payload {
+            parameters: None,
+            body: serde_json::json!({ "ref": git_ref }),
+        };
+
+        (headers, payload)
+    }
+
+    #[test]
+    /// Requests that match all filters aren't skipped
+    fn test_filters_match() {
+        let webhook = setup_webhook();
+
+        let (headers, payload) = request("push", "refs/heads/main");
+        assert_eq!(check_filters(&webhook, &headers, &payload), None);
+
+        let (headers, payload) = request("push", "refs/heads/release/1.0");
+        assert_eq!(check_filters(&webhook, &headers, &payload), None);
+    }
+
+    #[test]
+    /// Requests are skipped, if a single filter doesn't match
+    fn test_filters_skip() {
+        let webhook = setup_webhook();
+
+        let (headers, payload) = request("issue_comment", "refs/heads/main");
+        assert_eq!(
+            check_filters(&webhook, &headers, &payload),
+            Some("header X-GitHub-Event doesn't match: issue_comment".to_string())
+        );
+
+        let (headers, payload) = request("push", "refs/heads/feature");
+        assert!(check_filters(&webhook, &headers, &payload).is_some());
+
+        // The regex has to match the whole value
+        let (headers, payload) = request("push", "refs/heads/main-backup");
+        assert!(check_filters(&webhook, &headers, &payload).is_some());
+
+        let (_, payload) = request("push", "refs/heads/main");
+        assert_eq!(
+            check_filters(&webhook, &HashMap::new(), &payload),
+            Some("header X-GitHub-Event is missing".to_string())
+        );
+    }
+
+    #[test]
+    /// Globs can match arbitrary values
+    fn test_glob_filter() {
+        let webhook = Webhook {
+            filters: vec![Filter {
+                field: Some("/ref".to_string()),
+                glob: Some("refs/tags/v*".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let (headers, payload) = request("push", "refs/tags/v1.2.3");
+        assert_eq!(check_filters(&webhook, &headers, &payload), None);
+
+        let (headers, payload) = request("push", "refs/heads/v1");
+        assert!(check_filters(&webhook, &headers, &payload).is_some());
+    }
+
+    #[test]
+    /// Filters must specify exactly one source and one pattern
+    fn test_validate_filter() {
+        let filter = Filter {
+            header: Some("X-Gitlab-Event".to_string()),
+            glob: Some("Push Hook".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.validate().is_ok());
+
+        let mut invalid = filter.clone();
+        invalid.field = Some("/ref".to_string());
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = filter.clone();
+        invalid.regex = Some(".*".to_string());
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = filter;
+        invalid.glob = None;
+        invalid.regex = Some("(unclosed".to_string());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    /// Github's ping events are detected
+    fn test_github_ping() {
+        let (headers, _) = request("ping", "");
+        assert!(is_github_ping(&headers));
+
+        let (headers, _) = request("push", "");
+        assert!(!is_github_ping(&headers));
+    }
+}
@@ -1,18 +1,69 @@
 use std::collections::HashMap;
 
 use actix_web::{
-    error::{Error, ErrorBadRequest, ErrorUnauthorized},
+    HttpResponse,
+    error::{Error, ErrorBadRequest, ErrorInternalServerError, ErrorUnauthorized},
     http::header::HeaderMap,
 };
-use handlebars::Handlebars;
-use pueue_lib::message::AddRequest;
+use handlebars::{
+    Context,
+    Handlebars,
+    Helper,
+    HelperResult,
+    JsonRender,
+    Output,
+    RenderContext,
+    RenderErrorReason,
+    no_escape,
+};
+use pueue_lib::{Response, Task, TaskResult, TaskStatus, message::AddRequest};
+
+use crate::{
+    internal_prelude::*,
+    pueue::TaskLog,
+    settings::{Settings, Webhook, WebhookMode},
+    web::{
+        Payload,
+        TaskCreated,
+        TaskInfo,
+        TaskLogInfo,
+        WebhookQuery,
+        parameters::validate_parameters,
+    },
+};
 
-use crate::{internal_prelude::*, settings::Settings, web::Payload};
+/// All tasks that are created by this server are labeled with this prefix followed by the name
+/// of the webhook. This allows us to only expose tasks that have been created via webhooks.
+const TASK_LABEL_PREFIX: &str = "webhook:";
 
 /// We do our own json handling, since Actix doesn't allow multiple extractors at once
-pub fn get_payload(body: &[u8]) -> Result<Payload, Error> {
-    match serde_json::from_slice(body) {
-        Ok(payload) => Ok(payload),
+///
+/// Form encoded bodies, e.g. from Slack's slash commands, are converted into a json object, so
+/// their fields can be used via `parameter_mapping`.
+pub fn get_payload(body: &[u8], content_type: &str) -> Result<Payload, Error> {
+    if content_type == "application/x-www-form-urlencoded" {
+        let fields: Vec<(String, String)> =
+            serde_urlencoded::from_bytes(body).map_err(|error| {
+                let message = format!("Form error: {error}");
+                warn!("{message}");
+                ErrorBadRequest(message)
+            })?;
+        let body = fields
+            .into_iter()
+            .map(|(key, value)| (key, serde_json::Value::String(value)))
+            .collect();
+
+        return Ok(Payload {
+            parameters: None,
+            body: serde_json::Value::Object(body),
+        });
+    }
+
+    let parsed = serde_json::from_slice(body)
+        .and_then(|body: serde_json::Value| Ok((serde_json::from_value(body.clone())?, body)));
+
+    match parsed {
+        Ok((payload, body)) => Ok(Payload { body, ..payload }),
         Err(error) => {
             let message = format!("Json error: {error}");
             warn!("{message}");
@@ -42,17 +93,84 @@ pub fn get_headers_hash_map(map: &HeaderMap) -> Result<HashMap<String, String>,
     Ok(headers)
 }
 
-/// Verify that the template renders with the given parameters
+/// Verify that the template renders with the given parameters.
+/// Parameters are inserted as they are, without any escaping.
 pub fn verify_template_parameters(
     template: String,
     parameters: &HashMap<String, String>,
+) -> Result<String, Error> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(no_escape);
+
+    render_template(&handlebars, template, parameters)
+}
+
+/// Render the command of a webhook with the given parameters.
+///
+/// Webhooks either have a `command` that's rendered as a whole or a `program` with `args`.
+/// The latter is rendered argument by argument, after which each argument is shell-quoted. That
+/// way, a parameter can never end up as more than one argument.
+pub fn render_command(
+    webhook: &Webhook,
+    parameters: &HashMap<String, String>,
+) -> Result<String, Error> {
+    match (&webhook.command, &webhook.program) {
+        (Some(command), _) => render_shell_command(webhook, command, parameters),
+        (None, Some(program)) => {
+            let mut command = shell_quote(program);
+            for arg in webhook.args.iter() {
+                let arg = verify_template_parameters(arg.clone(), parameters)?;
+                command.push(' ');
+                command.push_str(&shell_quote(&arg));
+            }
+
+            Ok(command)
+        }
+        (None, None) => Err(ErrorInternalServerError(format!(
+            "Webhook {} has neither a command nor a program",
+            webhook.name
+        ))),
+    }
+}
+
+/// Render a command that's executed by the shell.
+///
+/// If `escape_parameters` is enabled, every parameter is shell-quoted, so it's always interpreted
+/// as a single word by the shell. `Webhook::validate` ensures that parameters are only used where
+/// this quoting works. The `{{raw name}}` helper allows to insert a parameter as
+/// it is, but it's only available if `allow_raw_parameters` is enabled.
+fn render_shell_command(
+    webhook: &Webhook,
+    command: &str,
+    parameters: &HashMap<String, String>,
+) -> Result<String, Error> {
+    let mut handlebars = Handlebars::new();
+    if webhook.escape_parameters {
+        handlebars.register_escape_fn(shell_quote);
+        // Handlebars has a builtin `raw` helper, which is replaced by ours.
+        handlebars.unregister_helper("raw");
+        if webhook.allow_raw_parameters {
+            handlebars.register_helper("raw", Box::new(raw_helper));
+        }
+    } else {
+        handlebars.register_escape_fn(no_escape);
+    }
+
+    render_template(&handlebars, command.to_string(), parameters)
+}
+
+/// Render a template and map errors to a proper response.
+fn render_template(
+    handlebars: &Handlebars,
+    template: String,
+    parameters: &HashMap<String, String>,
 ) -> Result<String, Error> {
     if !parameters.is_empty() {
         info!("Got parameters: {parameters:?}");
     }
     // Create a new handlebar instance and enable strict mode to prevent missing or malformed
     // arguments
-    let mut handlebars = Handlebars::new();
+    let mut handlebars = handlebars.clone();
     handlebars.set_strict_mode(true);
 
     // Check the template for render errors with the current parameter
@@ -71,6 +189,64 @@ pub fn verify_template_parameters(
     }
 }
 
+/// Quote a string, so it's interpreted as a single word by a POSIX shell.
+/// Strings that only consist of harmless characters are returned as they are.
+pub fn shell_quote(value: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-+=@%:,./".contains(c);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        return value.to_string();
+    }
+
+    // Single quotes prevent any interpretation by the shell.
+    // Single quotes themselves can't be escaped inside of single quotes, so we close the quoted
+    // string, add an escaped quote and start a new quoted string.
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Handlebars helper that renders a parameter without escaping.
+fn raw_helper(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let param = helper
+        .param(0)
+        .ok_or(RenderErrorReason::ParamNotFoundForIndex("raw", 0))?;
+    if param.is_value_missing() {
+        return Err(RenderErrorReason::MissingVariable(param.relative_path().cloned()).into());
+    }
+    out.write(&param.value().render())?;
+
+    Ok(())
+}
+
+/// Build the environment of a task.
+///
+/// Variables are applied in this order, later ones overwrite earlier ones:
+/// 1. Variables inherited from the server's environment.
+/// 2. Static variables from the `env` setting.
+/// 3. Variables from `parameter_env`, which are rendered with the request's parameters.
+pub fn get_task_envs(
+    webhook: &Webhook,
+    server_env: impl Iterator<Item = (String, String)>,
+    parameters: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, Error> {
+    let mut envs: HashMap<String, String> = server_env
+        .filter(|(key, _)| webhook.inherit_all_env || webhook.inherit_env.contains(key))
+        .collect();
+
+    envs.extend(webhook.env.clone());
+
+    for (key, template) in webhook.parameter_env.iter() {
+        let value = verify_template_parameters(template.clone(), parameters)?;
+        envs.insert(key.clone(), value);
+    }
+
+    Ok(envs)
+}
+
 /// Get a new task from a ingoing request
 pub fn get_task_from_request(
     settings: &Settings,
@@ -80,18 +256,665 @@ pub fn get_task_from_request(
     let parameters = parameters.unwrap_or_default();
 
     let webhook = settings.get_webhook_by_name(&name)?;
-    let command = verify_template_parameters(webhook.command, &parameters)?;
+    let parameters = validate_parameters(&webhook, parameters)?;
+    let command = render_command(&webhook, &parameters)?;
+    let envs = get_task_envs(&webhook, std::env::vars(), &parameters)?;
 
     Ok(AddRequest {
         command,
         path: webhook.cwd,
-        envs: std::env::vars().collect(),
-        group: "webhook".to_string(),
+        envs,
+        group: webhook.pueue_group,
         enqueue_at: None,
         dependencies: Vec::new(),
-        label: None,
+        label: Some(format!("{TASK_LABEL_PREFIX}{name}")),
         priority: None,
         start_immediately: false,
         stashed: false,
     })
 }
+
+/// Handle the daemon's response to an add request.
+/// Returns the created task or the error response that should be sent to the caller.
+pub fn get_created_task(
+    response: Response,
+    group: String,
+    command: String,
+) -> Result<TaskCreated, HttpResponse> {
+    match response {
+        Response::AddedTask(added) => {
+            info!("Created task {} in group \"{group}\"", added.task_id);
+            Ok(TaskCreated {
+                task_id: added.task_id,
+                group,
+                command,
+            })
+        }
+        Response::Failure(message) => {
+            warn!("Pueue daemon refused to add task: {message}");
+            Err(HttpResponse::BadGateway().body(format!("Pueue daemon refused task: {message}")))
+        }
+        response => {
+            warn!("Got unexpected response from Pueue daemon: {response:?}");
+            Err(HttpResponse::InternalServerError().body("Unexpected response from Pueue daemon"))
+        }
+    }
+}
+
+/// Whether a request should wait for its task to finish.
+/// That's the case for webhooks in sync mode or if the caller explicitly asks for it.
+pub fn should_wait(webhook: &Webhook, query: &WebhookQuery) -> bool {
+    query.wait || webhook.mode == WebhookMode::Sync
+}
+
+/// Build the response of a request that waited for its task.
+/// `log` is `None`, if the task didn't finish in time. In that case, the caller gets a
+/// `202 Accepted`, so it can fall back to polling the task.
+pub fn get_sync_response(created: TaskCreated, log: Option<TaskLog>) -> HttpResponse {
+    let Some(log) = log else {
+        return HttpResponse::Accepted().json(created);
+    };
+    let Some(info) = get_task_info(&log.task) else {
+        return HttpResponse::InternalServerError().body("Task vanished");
+    };
+
+    HttpResponse::Ok().json(TaskLogInfo {
+        task: info,
+        output: log.output,
+        output_complete: log.output_complete,
+    })
+}
+
+/// Get the name of the webhook that created a task.
+/// Returns `None` for tasks that haven't been created by this server.
+pub fn get_task_webhook(task: &Task) -> Option<&str> {
+    task.label.as_deref()?.strip_prefix(TASK_LABEL_PREFIX)
+}
+
+/// Get the publicly visible info of a task.
+/// Returns `None` for tasks that haven't been created by this server.
+pub fn get_task_info(task: &Task) -> Option<TaskInfo> {
+    let webhook = get_task_webhook(task)?;
+    let (start, end) = task.start_and_end();
+
+    let (result, exit_code) = match &task.status {
+        TaskStatus::Done { result, .. } => {
+            let exit_code = match result {
+                TaskResult::Success => Some(0),
+                TaskResult::Failed(code) => Some(*code),
+                _ => None,
+            };
+            (Some(result.to_string()), exit_code)
+        }
+        _ => (None, None),
+    };
+
+    Some(TaskInfo {
+        task_id: task.id,
+        webhook: webhook.to_string(),
+        group: task.group.clone(),
+        command: task.command.clone(),
+        status: task.status.to_string(),
+        start: start.map(|start| start.to_rfc3339()),
+        end: end.map(|end| end.to_rfc3339()),
+        result,
+        exit_code,
+    })
+}
+
+/// Format a single Server-Sent Event.
+/// Multi-line data is split into multiple `data:` fields, as SSE doesn't allow raw newlines.
+pub fn format_sse_event(event: &str, data: &str) -> String {
+    let mut message = format!("event: {event}\n");
+    for line in data.split('\n') {
+        message.push_str("data: ");
+        message.push_str(line);
+        message.push('\n');
+    }
+    message.push('\n');
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, http::StatusCode, web};
+    use chrono::Local;
+    use pueue_lib::message::AddedTaskResponse;
+
+    use super::*;
+    use crate::settings::Webhook;
+
+    fn setup_settings() -> Settings {
+        let webhook = |name: &str, group: &str| Webhook {
+            name: name.to_string(),
+            command: Some("/bin/ls {{param1}}".to_string()),
+            cwd: "/tmp".into(),
+            pueue_group: group.to_string(),
+            ..Default::default()
+        };
+
+        Settings {
+            webhooks: vec![webhook("deploy", "deploy"), webhook("render", "heavy")],
+            ..Default::default()
+        }
+    }
+
+    fn parameters() -> Option<HashMap<String, String>> {
+        Some(HashMap::from([("param1".to_string(), "-al".to_string())]))
+    }
+
+    #[test]
+    /// The task is added to the group of the webhook
+    fn test_task_uses_webhook_group() {
+        let settings = setup_settings();
+
+        let task = get_task_from_request(&settings, "deploy".to_string(), parameters()).unwrap();
+        assert_eq!(task.group, "deploy");
+        assert_eq!(task.command, "/bin/ls -al");
+
+        let task = get_task_from_request(&settings, "render".to_string(), parameters()).unwrap();
+        assert_eq!(task.group, "heavy");
+    }
+
+    #[test]
+    /// Tasks are labeled with the name of the webhook that created them
+    fn test_task_webhook_label() {
+        let settings = setup_settings();
+        let request = get_task_from_request(&settings, "deploy".to_string(), parameters()).unwrap();
+
+        let task = Task::new(
+            request.command,
+            request.path,
+            request.envs,
+            request.group,
+            TaskStatus::Queued {
+                enqueued_at: Local::now(),
+            },
+            request.dependencies,
+            0,
+            request.label,
+        );
+        assert_eq!(get_task_webhook(&task), Some("deploy"));
+
+        let info = get_task_info(&task).unwrap();
+        assert_eq!(info.webhook, "deploy");
+        assert_eq!(info.status, "Queued");
+        assert_eq!(info.start, None);
+        assert_eq!(info.exit_code, None);
+    }
+
+    #[actix_web::test]
+    /// The daemon's response to an add request is mapped to the right status codes
+    async fn test_created_task() {
+        let response = Response::AddedTask(AddedTaskResponse {
+            task_id: 42,
+            ..Default::default()
+        });
+        let created =
+            get_created_task(response, "webhook".to_string(), "/bin/ls".to_string()).unwrap();
+        let response = HttpResponse::Ok().json(created);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "task_id": 42, "group": "webhook", "command": "/bin/ls" })
+        );
+
+        let response = Response::Failure("Group doesn't exist".to_string());
+        let error = get_created_task(response, "webhook".to_string(), "/bin/ls".to_string());
+        assert_eq!(error.unwrap_err().status(), StatusCode::BAD_GATEWAY);
+
+        let response = Response::Success("Tasks have been killed".to_string());
+        let error = get_created_task(response, "webhook".to_string(), "/bin/ls".to_string());
+        assert_eq!(
+            error.unwrap_err().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    /// Webhooks in sync mode always wait, others only if the caller asks for it
+    fn test_should_wait() {
+        let settings: Settings = serde_yaml::from_str(
+            r#"
+domain: 127.0.0.1
+port: 8000
+webhooks:
+  - name: sync
+    command: /bin/ls
+    cwd: /tmp
+    mode: sync
+  - name: async
+    command: /bin/ls
+    cwd: /tmp
+"#,
+        )
+        .unwrap();
+        let sync = settings.get_webhook_by_name("sync").unwrap();
+        let not_sync = settings.get_webhook_by_name("async").unwrap();
+        assert_eq!(sync.mode, WebhookMode::Sync);
+        assert_eq!(not_sync.mode, WebhookMode::Async);
+
+        let query = web::Query::<WebhookQuery>::from_query("").unwrap();
+        assert!(should_wait(&sync, &query));
+        assert!(!should_wait(&not_sync, &query));
+
+        let query = web::Query::<WebhookQuery>::from_query("wait=true").unwrap();
+        assert!(should_wait(&not_sync, &query));
+    }
+
+    #[actix_web::test]
+    /// Tasks that don't finish in time are answered with their id, so the caller can poll them
+    async fn test_sync_response() {
+        let created = || TaskCreated {
+            task_id: 42,
+            group: "webhook".to_string(),
+            command: "/bin/ls".to_string(),
+        };
+
+        let response = get_sync_response(created(), None);
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "task_id": 42, "group": "webhook", "command": "/bin/ls" })
+        );
+
+        let now = Local::now();
+        let mut task = Task::new(
+            "/bin/ls".to_string(),
+            "/tmp".into(),
+            HashMap::new(),
+            "webhook".to_string(),
+            TaskStatus::Done {
+                enqueued_at: now,
+                start: now,
+                end: now,
+                result: TaskResult::Success,
+            },
+            Vec::new(),
+            0,
+            Some("webhook:ls".to_string()),
+        );
+        task.id = 42;
+        let log = TaskLog {
+            task,
+            output: "some output".to_string(),
+            output_complete: true,
+        };
+
+        let response = get_sync_response(created(), Some(log));
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["task_id"], 42);
+        assert_eq!(body["exit_code"], 0);
+        assert_eq!(body["output"], "some output");
+        assert_eq!(body["output_complete"], true);
+    }
+
+    #[test]
+    /// Finished tasks expose their result and exit code
+    fn test_finished_task_info() {
+        let now = Local::now();
+        let task = Task::new(
+            "/bin/false".to_string(),
+            "/tmp".into(),
+            HashMap::new(),
+            "webhook".to_string(),
+            TaskStatus::Done {
+                enqueued_at: now,
+                start: now,
+                end: now,
+                result: TaskResult::Failed(1),
+            },
+            Vec::new(),
+            0,
+            Some("webhook:ls".to_string()),
+        );
+
+        let info = get_task_info(&task).unwrap();
+        assert_eq!(info.status, "Done");
+        assert_eq!(info.result, Some("Failed".to_string()));
+        assert_eq!(info.exit_code, Some(1));
+        assert_eq!(info.start, Some(now.to_rfc3339()));
+        assert_eq!(info.end, Some(now.to_rfc3339()));
+    }
+
+    #[test]
+    /// Tasks that haven't been created by this server aren't exposed
+    fn test_foreign_task_info() {
+        let mut task = Task::new(
+            "/bin/true".to_string(),
+            "/tmp".into(),
+            HashMap::new(),
+            "default".to_string(),
+            TaskStatus::Queued {
+                enqueued_at: Local::now(),
+            },
+            Vec::new(),
+            0,
+            None,
+        );
+        assert!(get_task_info(&task).is_none());
+
+        task.label = Some("some label".to_string());
+        assert!(get_task_info(&task).is_none());
+    }
+
+    #[test]
+    /// Webhooks without an explicit group end up in the default `webhook` group
+    fn test_task_uses_default_group() {
+        let settings: Settings = serde_yaml::from_str(
+            r#"
+domain: 127.0.0.1
+port: 8000
+webhooks:
+  - name: ls
+    command: /bin/ls
+    cwd: /tmp
+"#,
+        )
+        .unwrap();
+
+        let task = get_task_from_request(&settings, "ls".to_string(), None).unwrap();
+        assert_eq!(task.group, "webhook");
+    }
+
+    #[test]
+    /// Requests for unknown webhooks are rejected
+    fn test_unknown_webhook() {
+        let settings = setup_settings();
+        assert!(get_task_from_request(&settings, "unknown".to_string(), None).is_err());
+    }
+
+    #[test]
+    /// Multi-line output is split into multiple data fields
+    fn test_format_sse_event() {
+        assert_eq!(
+            format_sse_event("output", "line 1\nline 2"),
+            "event: output\ndata: line 1\ndata: line 2\n\n"
+        );
+        assert_eq!(
+            format_sse_event("finished", "{}"),
+            "event: finished\ndata: {}\n\n"
+        );
+    }
+
+    fn server_env() -> impl Iterator<Item = (String, String)> {
+        [
+            ("PATH", "/usr/bin"),
+            ("HOME", "/home/webhook"),
+            ("SECRET_TOKEN", "hunter2"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+    }
+
+    #[test]
+    /// Only allowed variables are inherited from the server's environment by default
+    fn test_task_envs_minimal() {
+        let webhook = Webhook::default();
+        let envs = get_task_envs(&webhook, server_env(), &HashMap::new()).unwrap();
+
+        assert_eq!(envs.get("PATH").map(String::as_str), Some("/usr/bin"));
+        assert_eq!(envs.get("HOME").map(String::as_str), Some("/home/webhook"));
+        assert!(!envs.contains_key("SECRET_TOKEN"));
+
+        let webhook = Webhook {
+            inherit_env: Vec::new(),
+            ..Default::default()
+        };
+        let envs = get_task_envs(&webhook, server_env(), &HashMap::new()).unwrap();
+        assert!(envs.is_empty());
+    }
+
+    #[test]
+    /// The whole environment is inherited in compatibility mode
+    fn test_task_envs_inherit_all() {
+        let webhook = Webhook {
+            inherit_all_env: true,
+            ..Default::default()
+        };
+        let envs = get_task_envs(&webhook, server_env(), &HashMap::new()).unwrap();
+
+        assert_eq!(envs.len(), 3);
+        assert_eq!(
+            envs.get("SECRET_TOKEN").map(String::as_str),
+            Some("hunter2")
+        );
+    }
+
+    #[test]
+    /// Static and parameter variables are added and overwrite inherited ones
+    fn test_task_envs_static_and_parameters() {
+        let webhook = Webhook {
+            env: HashMap::from([
+                ("PATH".to_string(), "/opt/bin".to_string()),
+                ("STAGE".to_string(), "production".to_string()),
+            ]),
+            parameter_env: HashMap::from([("DEPLOY_REF".to_string(), "{{ref}}".to_string())]),
+            ..Default::default()
+        };
+        let parameters = HashMap::from([("ref".to_string(), "main".to_string())]);
+        let envs = get_task_envs(&webhook, server_env(), &parameters).unwrap();
+
+        assert_eq!(envs.get("PATH").map(String::as_str), Some("/opt/bin"));
+        assert_eq!(envs.get("STAGE").map(String::as_str), Some("production"));
+        assert_eq!(envs.get("DEPLOY_REF").map(String::as_str), Some("main"));
+
+        // Missing parameters are rejected
+        assert!(get_task_envs(&webhook, server_env(), &HashMap::new()).is_err());
+    }
+
+    /// Render a command with a single parameter and split it like a shell would
+    fn render_and_split(webhook: &Webhook, value: &str) -> Vec<String> {
+        let parameters = HashMap::from([("param".to_string(), value.to_string())]);
+        let command = render_command(webhook, &parameters).unwrap();
+        shlex::split(&command).unwrap()
+    }
+
+    #[test]
+    /// Injection attempts are always rendered as a single argument
+    fn test_escaped_injection_attempts() {
+        let webhook = Webhook {
+            command: Some("/bin/echo {{param}}".to_string()),
+            ..Default::default()
+        };
+
+        for value in [
+            "; rm -rf /",
+            "`rm -rf /`",
+            "$(rm -rf /)",
+            "foo\nrm -rf /",
+            "' ; rm -rf / ; '",
+            "\" ; rm -rf / ; \"",
+            "\"$HOME\"",
+            "a && b || c | d > e",
+            "",
+        ] {
+            assert_eq!(
+                render_and_split(&webhook, value),
+                vec!["/bin/echo".to_string(), value.to_string()],
+                "{value:?} wasn't escaped properly"
+            );
+        }
+
+        // Parameters are refused where single quotes don't protect them
+        for command in [
+            "/bin/echo \"{{param}}\"",
+            "/bin/echo '{{param}}'",
+            "/bin/echo \"{{ param }}\"",
+            "/bin/echo \"$(printf %s {{param}})\"",
+            "/bin/echo $(printf %s {{param}})",
+            "/bin/echo `printf %s {{param}}`",
+            "/bin/echo ${param:-{{param}}}",
+            "/bin/echo $'{{param}}'",
+            "/bin/cat <<EOF\n{{param}}\nEOF",
+            "/bin/echo {{lookup this \"param\"}}",
+            "/bin/echo \"{{lookup this \"param\"}}\"",
+            "/bin/echo {{#if param}}{{param}}{{/if}}",
+        ] {
+            let webhook = Webhook {
+                command: Some(command.to_string()),
+                ..Default::default()
+            };
+            let error = webhook.validate().unwrap_err();
+            assert!(
+                format!("{error:?}").contains("Invalid command template"),
+                "{command} wasn't refused"
+            );
+        }
+
+        // Escaped quotes and closed quotes don't affect later parameters
+        for command in [
+            "/bin/echo \\\" {{param}}",
+            "/bin/echo 'a \"' {{param}}",
+            "/bin/echo \"it's\" {{param}} $HOME",
+            "/bin/echo {{~param~}} {{raw param}}",
+        ] {
+            let webhook = Webhook {
+                command: Some(command.to_string()),
+                allow_raw_parameters: true,
+                ..Default::default()
+            };
+            assert!(webhook.validate().is_ok(), "{command} was refused");
+        }
+    }
+
+    #[test]
+    /// Harmless values aren't quoted
+    fn test_escaped_harmless_values() {
+        let webhook = Webhook {
+            command: Some("/bin/ls {{param1}}".to_string()),
+            ..Default::default()
+        };
+        let parameters = HashMap::from([("param1".to_string(), "-al".to_string())]);
+        assert_eq!(
+            render_command(&webhook, &parameters).unwrap(),
+            "/bin/ls -al"
+        );
+
+        assert_eq!(shell_quote("/tmp/some_dir"), "/tmp/some_dir");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    /// The raw helper is only available if it's explicitly allowed
+    fn test_raw_parameters() {
+        let parameters = HashMap::from([("param".to_string(), "-al /tmp".to_string())]);
+        let mut webhook = Webhook {
+            command: Some("/bin/ls {{raw param}}".to_string()),
+            ..Default::default()
+        };
+        assert!(render_command(&webhook, &parameters).is_err());
+
+        webhook.allow_raw_parameters = true;
+        assert_eq!(
+            render_command(&webhook, &parameters).unwrap(),
+            "/bin/ls -al /tmp"
+        );
+
+        // Missing parameters are still detected
+        webhook.command = Some("/bin/ls {{raw missing}}".to_string());
+        assert!(render_command(&webhook, &parameters).is_err());
+    }
+
+    #[test]
+    /// Parameters are inserted as they are, if escaping is disabled
+    fn test_unescaped_parameters() {
+        let webhook = Webhook {
+            command: Some("/bin/echo {{param}}".to_string()),
+            escape_parameters: false,
+            ..Default::default()
+        };
+        let parameters = HashMap::from([("param".to_string(), "a & b".to_string())]);
+        assert_eq!(
+            render_command(&webhook, &parameters).unwrap(),
+            "/bin/echo a & b"
+        );
+    }
+
+    #[test]
+    /// Unescaped handlebars expressions are rejected, if escaping is enabled
+    fn test_reject_triple_stash() {
+        let mut webhook = Webhook {
+            command: Some("/bin/echo {{{param}}}".to_string()),
+            ..Default::default()
+        };
+        assert!(webhook.validate().is_err());
+
+        webhook.command = Some("/bin/echo {{&param}}".to_string());
+        assert!(webhook.validate().is_err());
+
+        webhook.escape_parameters = false;
+        assert!(webhook.validate().is_ok());
+    }
+
+    #[test]
+    /// Every argument of a program is a single argument, regardless of its content
+    fn test_program_args() {
+        let webhook = Webhook {
+            program: Some("/usr/local/bin/deploy script".to_string()),
+            args: vec![
+                "--branch".to_string(),
+                "{{param}}".to_string(),
+                "{{param}} and more".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        for value in ["; rm -rf /", "$(id)", "a b c", "{{other}}"] {
+            assert_eq!(
+                render_and_split(&webhook, value),
+                vec![
+                    "/usr/local/bin/deploy script".to_string(),
+                    "--branch".to_string(),
+                    value.to_string(),
+                    format!("{value} and more"),
+                ],
+                "{value:?} wasn't escaped properly"
+            );
+        }
+
+        // Missing parameters are detected
+        assert!(render_command(&webhook, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    /// Either command or program has to be specified
+    fn test_command_or_program() {
+        let mut webhook = Webhook {
+            command: Some("/bin/ls".to_string()),
+            program: Some("/bin/ls".to_string()),
+            ..Default::default()
+        };
+        assert!(webhook.validate().is_err());
+
+        webhook.command = None;
+        assert!(webhook.validate().is_ok());
+
+        webhook.program = None;
+        assert!(webhook.validate().is_err());
+
+        webhook.command = Some("/bin/ls".to_string());
+        webhook.args = vec!["-al".to_string()];
+        assert!(webhook.validate().is_err());
+    }
+
+    #[test]
+    /// Form encoded bodies are converted into a json object
+    fn test_form_payload() {
+        let body = b"command=%2Fdeploy&text=production+now";
+        let payload = get_payload(body, "application/x-www-form-urlencoded").unwrap();
+
+        assert_eq!(payload.parameters, None);
+        assert_eq!(
+            payload.body,
+            serde_json::json!({ "command": "/deploy", "text": "production now" })
+        );
+
+        assert!(get_payload(body, "application/json").is_err());
+    }
+}
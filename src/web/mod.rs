@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fs::File, io::BufReader, path::PathBuf};
+use std::{collections::HashMap, fs::File, io::BufReader, path::PathBuf, sync::Mutex};
 
 use actix_web::{App, HttpServer, web};
 use rustls::{
@@ -6,12 +6,19 @@ use rustls::{
     pki_types::{CertificateDer, PrivateKeyDer},
 };
 use rustls_pemfile::{pkcs8_private_keys, rsa_private_keys};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 mod authentication;
+mod client_cert;
+mod filters;
 mod helper;
+mod networks;
+pub mod parameters;
+mod replay;
 mod routes;
 
+use client_cert::{get_client_cert_verifier, store_client_identity};
+use replay::DeliveryCache;
 use routes::*;
 
 use crate::{internal_prelude::*, settings::Settings};
@@ -19,26 +26,100 @@ use crate::{internal_prelude::*, settings::Settings};
 /// State of the actix-web application
 pub struct AppState {
     settings: Settings,
+    /// The delivery ids of recent requests, which is shared by all workers.
+    delivery_ids: Mutex<DeliveryCache>,
 }
 
 #[derive(Deserialize, Debug, Default)]
 pub struct Payload {
     parameters: Option<HashMap<String, String>>,
+    /// The whole json body of the request, which can be used to extract parameters.
+    #[serde(skip)]
+    body: serde_json::Value,
+}
+
+/// The response that's sent after a task has been successfully added to Pueue.
+#[derive(Serialize, Debug)]
+pub struct TaskCreated {
+    task_id: usize,
+    group: String,
+    command: String,
+}
+
+/// The status of a task that has been created by a webhook.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct TaskInfo {
+    task_id: usize,
+    webhook: String,
+    group: String,
+    command: String,
+    status: String,
+    start: Option<String>,
+    end: Option<String>,
+    result: Option<String>,
+    exit_code: Option<i32>,
+}
+
+/// The format in which a task's log is returned.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Query parameters of the webhook route.
+#[derive(Deserialize, Debug, Default)]
+pub struct WebhookQuery {
+    /// Wait for the task to finish, even if the webhook isn't in sync mode.
+    #[serde(default)]
+    wait: bool,
+}
+
+/// Query parameters of the task follow route.
+#[derive(Deserialize, Debug, Default)]
+pub struct FollowQuery {
+    /// Start with the last `lines` lines of already existing output.
+    lines: Option<usize>,
+}
+
+/// Query parameters of the task log route.
+#[derive(Deserialize, Debug, Default)]
+pub struct LogQuery {
+    /// Only return the last `lines` lines of output.
+    lines: Option<usize>,
+    #[serde(default)]
+    format: LogFormat,
+}
+
+/// The log output of a task that has been created by a webhook.
+#[derive(Serialize, Debug)]
+pub struct TaskLogInfo {
+    #[serde(flatten)]
+    task: TaskInfo,
+    output: String,
+    output_complete: bool,
 }
 
 /// Initialize the web server
 /// Move the address of the queue actor inside the AppState for further dispatch
 /// of tasks to the actor
 pub async fn run_web_server(settings: Settings) -> Result<()> {
-    let settings_for_app = settings.clone();
+    let state = web::Data::new(AppState {
+        settings: settings.clone(),
+        delivery_ids: Mutex::new(DeliveryCache::new(settings.delivery_id_cache_size)),
+    });
     let server = HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(AppState {
-                settings: settings_for_app.clone(),
-            }))
+            .app_data(state.clone())
+            .service(web::resource("/tasks/{task_id}").route(web::get().to(task_status)))
+            .service(web::resource("/tasks/{task_id}/log").route(web::get().to(task_log)))
+            .service(web::resource("/tasks/{task_id}/follow").route(web::get().to(follow_task)))
             .service(web::resource("/{webhook_name}").to(webhook))
         //.service(web::resource("/").to(index))
     })
+    .on_connect(store_client_identity)
     .workers(2);
 
     let address = format!("{}:{}", settings.domain, settings.port);
@@ -57,8 +138,12 @@ pub async fn run_web_server(settings: Settings) -> Result<()> {
         let certs = load_certs(PathBuf::from(chain_path))?;
         let key = load_key(PathBuf::from(key_path))?;
 
-        let config = ServerConfig::builder()
-            .with_no_client_auth()
+        let builder = ServerConfig::builder();
+        let builder = match get_client_cert_verifier(&settings)? {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
             .with_single_cert(certs, key)
             .context("Failed to build server TLS config.".to_string())?;
 
@@ -0,0 +1,189 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+};
+
+use actix_web::error::{Error, ErrorForbidden};
+use ipnet::IpNet;
+
+use crate::{
+    internal_prelude::*,
+    settings::{Settings, Webhook},
+};
+
+/// Check whether the client of a request is in the allowed networks.
+///
+/// The allowed networks of the targeted webhook take precedence over the global ones.
+/// If no networks are configured, all clients are allowed.
+pub fn verify_client_network(
+    settings: &Settings,
+    webhook: Option<&Webhook>,
+    peer: Option<SocketAddr>,
+    headers: &HashMap<String, String>,
+) -> Result<(), Error> {
+    let allowed_networks = webhook
+        .and_then(|webhook| webhook.allowed_networks.as_ref())
+        .unwrap_or(&settings.allowed_networks);
+    if allowed_networks.is_empty() {
+        return Ok(());
+    }
+
+    let peer = peer.map(|peer| peer.ip());
+    let Some(client_ip) = resolve_client_ip(peer, headers, &settings.trusted_proxies) else {
+        warn!("Refused request, couldn't resolve the client IP of peer {peer:?}");
+        return Err(ErrorForbidden("Forbidden"));
+    };
+
+    if !allowed_networks
+        .iter()
+        .any(|network| network.contains(&client_ip))
+    {
+        warn!("Refused request from {client_ip}, which isn't in the allowed networks");
+        return Err(ErrorForbidden("Forbidden"));
+    }
+
+    debug!("Accepted request from {client_ip}");
+    Ok(())
+}
+
+/// Get the IP of the client that sent a request.
+///
+/// The `X-Forwarded-For` header is only believed, if the request comes from a trusted proxy.
+/// The header is read from right to left and the first address that isn't a trusted proxy is the
+/// client, as everything before that could've been sent by the client itself.
+pub fn resolve_client_ip(
+    peer: Option<IpAddr>,
+    headers: &HashMap<String, String>,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let mut client_ip = peer?.to_canonical();
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+    if !is_trusted(&client_ip) {
+        return Some(client_ip);
+    }
+
+    let Some(forwarded_for) = headers.get("x-forwarded-for") else {
+        return Some(client_ip);
+    };
+
+    for hop in forwarded_for.rsplit(',') {
+        // Malformed entries could be anything, so the client can't be determined.
+        let ip = hop.trim().parse::<IpAddr>().ok()?.to_canonical();
+        client_ip = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+
+    Some(client_ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn networks(networks: &[&str]) -> Vec<IpNet> {
+        networks
+            .iter()
+            .map(|network| network.parse().unwrap())
+            .collect()
+    }
+
+    fn forwarded_for(value: &str) -> HashMap<String, String> {
+        HashMap::from([("x-forwarded-for".to_string(), value.to_string())])
+    }
+
+    #[test]
+    /// Forwarded headers are only believed for trusted proxies
+    fn test_resolve_client_ip() {
+        let trusted = networks(&["127.0.0.1/32", "10.0.0.0/8"]);
+        let headers = forwarded_for("192.0.2.1, 10.0.0.5");
+
+        // Untrusted peers can't spoof their address
+        assert_eq!(
+            resolve_client_ip(Some(ip("198.51.100.7")), &headers, &trusted),
+            Some(ip("198.51.100.7"))
+        );
+
+        // The first untrusted hop from the right is the client
+        assert_eq!(
+            resolve_client_ip(Some(ip("127.0.0.1")), &headers, &trusted),
+            Some(ip("192.0.2.1"))
+        );
+        let headers = forwarded_for("10.1.1.1, 203.0.113.9, 10.0.0.5");
+        assert_eq!(
+            resolve_client_ip(Some(ip("127.0.0.1")), &headers, &trusted),
+            Some(ip("203.0.113.9"))
+        );
+
+        // Without a header, the proxy is the client
+        assert_eq!(
+            resolve_client_ip(Some(ip("127.0.0.1")), &HashMap::new(), &trusted),
+            Some(ip("127.0.0.1"))
+        );
+
+        // Malformed headers from trusted proxies can't be resolved
+        let headers = forwarded_for("unknown, 10.0.0.5");
+        assert_eq!(
+            resolve_client_ip(Some(ip("127.0.0.1")), &headers, &trusted),
+            None
+        );
+    }
+
+    #[test]
+    /// IPv4 addresses that're mapped to IPv6 are matched as IPv4
+    fn test_resolve_mapped_ip() {
+        let trusted = networks(&["127.0.0.1/32"]);
+        assert_eq!(
+            resolve_client_ip(Some(ip("::ffff:192.0.2.1")), &HashMap::new(), &trusted),
+            Some(ip("192.0.2.1"))
+        );
+    }
+
+    #[test]
+    /// Requests from outside the allowed networks are refused
+    fn test_allowed_networks() {
+        let settings = Settings {
+            allowed_networks: networks(&["192.0.2.0/24"]),
+            trusted_proxies: networks(&["127.0.0.1/32"]),
+            ..Default::default()
+        };
+        let proxy = Some(SocketAddr::new(ip("127.0.0.1"), 4000));
+        let headers = forwarded_for("192.0.2.1");
+
+        assert!(verify_client_network(&settings, None, proxy, &headers).is_ok());
+        assert!(verify_client_network(&settings, None, proxy, &forwarded_for("1.2.3.4")).is_err());
+        assert!(verify_client_network(&settings, None, None, &headers).is_err());
+
+        let error = verify_client_network(&settings, None, proxy, &HashMap::new()).unwrap_err();
+        assert_eq!(error.as_response_error().status_code().as_u16(), 403);
+    }
+
+    #[test]
+    /// The allowed networks of a webhook take precedence over the global ones
+    fn test_webhook_allowed_networks() {
+        let settings = Settings {
+            allowed_networks: networks(&["192.0.2.0/24"]),
+            ..Default::default()
+        };
+        let peer = Some(SocketAddr::new(ip("10.0.0.1"), 4000));
+
+        let webhook = Webhook {
+            allowed_networks: Some(networks(&["10.0.0.0/8"])),
+            ..Default::default()
+        };
+        assert!(verify_client_network(&settings, Some(&webhook), peer, &HashMap::new()).is_ok());
+        assert!(verify_client_network(&settings, None, peer, &HashMap::new()).is_err());
+
+        // An empty list allows all clients
+        let webhook = Webhook {
+            allowed_networks: Some(Vec::new()),
+            ..Default::default()
+        };
+        assert!(verify_client_network(&settings, Some(&webhook), peer, &HashMap::new()).is_ok());
+    }
+}
@@ -0,0 +1,431 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path},
+};
+
+use actix_web::{
+    HttpResponse,
+    error::{Error, ErrorBadRequest, InternalError},
+};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    internal_prelude::*,
+    settings::{Parameter, ParameterType, Webhook},
+    web::Payload,
+};
+
+/// A single parameter that failed validation.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ParameterError {
+    field: String,
+    message: String,
+}
+
+/// The body of a response to a request with invalid parameters.
+#[derive(Serialize, Debug)]
+struct ParameterErrors {
+    errors: Vec<ParameterError>,
+}
+
+/// Get the parameters of a request.
+///
+/// The `parameters` object of the payload is used by default.
+/// Values from the webhook's `parameter_mapping` are extracted from the whole json body and take
+/// precedence. Mappings that point to a missing or `null` value are ignored.
+pub fn extract_parameters(
+    webhook: &Webhook,
+    payload: &Payload,
+) -> Result<HashMap<String, String>, Error> {
+    let mut parameters = payload.parameters.clone().unwrap_or_default();
+
+    for (name, pointer) in webhook.parameter_mapping.iter() {
+        let value = match payload.body.pointer(pointer) {
+            None | Some(Value::Null) => continue,
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Bool(value)) => value.to_string(),
+            Some(Value::Number(value)) => value.to_string(),
+            Some(Value::Array(_) | Value::Object(_)) => {
+                warn!("Mapping for parameter {name} points to a non-scalar value");
+                return Err(ErrorBadRequest(format!(
+                    "Parameter {name} must be a string, number or boolean"
+                )));
+            }
+        };
+        parameters.insert(name.clone(), value);
+    }
+
+    Ok(parameters)
+}
+
+/// Validate the parameters of a request against the webhook's parameter declarations.
+///
+/// Missing optional parameters are filled with their default values.
+/// If the webhook doesn't declare any parameters, all parameters are accepted as they are.
+///
+/// Invalid requests get a `422` response with a list of all failing fields.
+pub fn validate_parameters(
+    webhook: &Webhook,
+    parameters: HashMap<String, String>,
+) -> Result<HashMap<String, String>, Error> {
+    if webhook.parameters.is_empty() {
+        return Ok(parameters);
+    }
+
+    match check_parameters(webhook, parameters) {
+        Ok(parameters) => Ok(parameters),
+        Err(errors) => {
+            warn!("Got invalid parameters: {errors:?}");
+            let response = HttpResponse::UnprocessableEntity().json(ParameterErrors { errors });
+            Err(InternalError::from_response("Invalid parameters", response).into())
+        }
+    }
+}
+
+/// Check all parameters and collect every error.
+fn check_parameters(
+    webhook: &Webhook,
+    mut parameters: HashMap<String, String>,
+) -> Result<HashMap<String, String>, Vec<ParameterError>> {
+    let mut errors = Vec::new();
+
+    for name in parameters.keys() {
+        if !webhook.parameters.contains_key(name) {
+            errors.push(ParameterError {
+                field: name.clone(),
+                message: "Unknown parameter".to_string(),
+            });
+        }
+    }
+
+    for (name, declaration) in webhook.parameters.iter() {
+        let Some(value) = parameters.get(name) else {
+            if let Some(default) = &declaration.default {
+                parameters.insert(name.clone(), default.clone());
+            } else if declaration.required {
+                errors.push(ParameterError {
+                    field: name.clone(),
+                    message: "Missing required parameter".to_string(),
+                });
+            }
+            continue;
+        };
+
+        if let Err(message) = check_value(declaration, value) {
+            errors.push(ParameterError {
+                field: name.clone(),
+                message,
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(parameters)
+    } else {
+        errors.sort_by(|a, b| a.field.cmp(&b.field));
+        Err(errors)
+    }
+}
+
+/// Check a single value against its declaration.
+pub fn check_value(declaration: &Parameter, value: &str) -> Result<(), String> {
+    if let Some(max_length) = declaration.max_length
+        && value.chars().count() > max_length
+    {
+        return Err(format!("Value is longer than {max_length} characters"));
+    }
+
+    match declaration.parameter_type {
+        ParameterType::String => (),
+        ParameterType::Int => {
+            if value.parse::<i64>().is_err() {
+                return Err("Value isn't an integer".to_string());
+            }
+        }
+        ParameterType::Bool => {
+            if value != "true" && value != "false" {
+                return Err("Value must be either true or false".to_string());
+            }
+        }
+        ParameterType::Path => {
+            let path = Path::new(value);
+            if value.is_empty() || value.contains('\0') {
+                return Err("Value isn't a valid path".to_string());
+            }
+            if path.components().any(|c| c == Component::ParentDir) {
+                return Err("Path must not contain '..'".to_string());
+            }
+        }
+    }
+
+    if let Some(allowed_values) = &declaration.allowed_values
+        && !allowed_values.iter().any(|allowed| allowed == value)
+    {
+        return Err(format!(
+            "Value must be one of: {}",
+            allowed_values.join(", ")
+        ));
+    }
+
+    if let Some(pattern) = &declaration.pattern {
+        // The pattern has to match the whole value.
+        let regex = Regex::new(&format!("^(?:{pattern})$"))
+            .map_err(|_| "Invalid pattern in configuration".to_string())?;
+        if !regex.is_match(value) {
+            return Err(format!("Value doesn't match pattern {pattern}"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_webhook() -> Webhook {
+        let parameters = HashMap::from([
+            (
+                "branch".to_string(),
+                Parameter {
+                    required: true,
+                    pattern: Some("[a-z0-9/_-]+".to_string()),
+                    max_length: Some(20),
+                    ..Default::default()
+                },
+            ),
+            (
+                "count".to_string(),
+                Parameter {
+                    default: Some("1".to_string()),
+                    parameter_type: ParameterType::Int,
+                    ..Default::default()
+                },
+            ),
+            (
+                "stage".to_string(),
+                Parameter {
+                    allowed_values: Some(vec!["staging".to_string(), "production".to_string()]),
+                    ..Default::default()
+                },
+            ),
+            (
+                "dry_run".to_string(),
+                Parameter {
+                    parameter_type: ParameterType::Bool,
+                    ..Default::default()
+                },
+            ),
+            (
+                "dir".to_string(),
+                Parameter {
+                    parameter_type: ParameterType::Path,
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        Webhook {
+            parameters,
+            ..Default::default()
+        }
+    }
+
+    fn parameters(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn error_fields(errors: Vec<ParameterError>) -> Vec<String> {
+        errors.into_iter().map(|error| error.field).collect()
+    }
+
+    #[test]
+    /// Valid parameters are accepted and defaults are filled in
+    fn test_valid_parameters() {
+        let webhook = setup_webhook();
+        let result = check_parameters(
+            &webhook,
+            parameters(&[
+                ("branch", "feature/foo"),
+                ("stage", "production"),
+                ("dry_run", "false"),
+                ("dir", "/srv/app"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(result.get("count").map(String::as_str), Some("1"));
+        assert_eq!(
+            result.get("branch").map(String::as_str),
+            Some("feature/foo")
+        );
+    }
+
+    #[test]
+    /// All invalid fields are reported at once
+    fn test_invalid_parameters() {
+        let webhook = setup_webhook();
+        let errors = check_parameters(
+            &webhook,
+            parameters(&[
+                ("count", "ten"),
+                ("stage", "development"),
+                ("dry_run", "yes"),
+                ("dir", "../etc"),
+                ("unknown", "value"),
+            ]),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error_fields(errors),
+            vec!["branch", "count", "dir", "dry_run", "stage", "unknown"]
+        );
+    }
+
+    #[test]
+    /// The pattern has to match the whole value and the length is limited
+    fn test_pattern_and_length() {
+        let webhook = setup_webhook();
+
+        let errors =
+            check_parameters(&webhook, parameters(&[("branch", "main; rm -rf /")])).unwrap_err();
+        assert_eq!(error_fields(errors), vec!["branch"]);
+
+        let errors = check_parameters(
+            &webhook,
+            parameters(&[("branch", "a-very-long-branch-name")]),
+        )
+        .unwrap_err();
+        assert_eq!(error_fields(errors), vec!["branch"]);
+    }
+
+    #[test]
+    /// Defaults have to satisfy their own declaration, otherwise the config is refused
+    fn test_invalid_defaults() {
+        let mut webhook = setup_webhook();
+        webhook.command = Some("/bin/ls".to_string());
+        assert!(webhook.validate().is_ok());
+
+        let invalid_defaults = [
+            ("count", "abc"),
+            ("branch", "main; rm -rf /"),
+            ("branch", "a-very-long-branch-name"),
+            ("stage", "development"),
+            ("dry_run", "yes"),
+            ("dir", "../etc"),
+        ];
+        for (name, default) in invalid_defaults {
+            let mut webhook = webhook.clone();
+            webhook.parameters.get_mut(name).unwrap().default = Some(default.to_string());
+            let error = webhook.validate().unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains(&format!("Invalid default for parameter {name}")),
+                "{name}: {default} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    /// Webhooks without declarations accept any parameters
+    fn test_no_declarations() {
+        let webhook = Webhook::default();
+        let result = validate_parameters(&webhook, parameters(&[("anything", "; ls")])).unwrap();
+        assert_eq!(result.get("anything").map(String::as_str), Some("; ls"));
+    }
+
+    #[test]
+    /// Invalid requests are answered with 422
+    fn test_unprocessable_entity() {
+        let webhook = setup_webhook();
+        let error = validate_parameters(&webhook, HashMap::new()).unwrap_err();
+        assert_eq!(error.as_response_error().status_code().as_u16(), 422);
+    }
+
+    fn github_payload() -> Payload {
+        let body: Value = serde_json::from_str(
+            r#"{
+                "ref": "refs/heads/main",
+                "head_commit": { "id": "abc123", "distinct": true },
+                "repository": { "id": 42, "name": "webhook-server" },
+                "pusher": null
+            }"#,
+        )
+        .unwrap();
+
+        Payload {
+            parameters: None,
+            body,
+        }
+    }
+
+    #[test]
+    /// Parameters can be extracted from arbitrary json bodies
+    fn test_extract_parameters() {
+        let webhook = Webhook {
+            parameter_mapping: HashMap::from([
+                ("ref".to_string(), "/ref".to_string()),
+                ("sha".to_string(), "/head_commit/id".to_string()),
+                ("distinct".to_string(), "/head_commit/distinct".to_string()),
+                ("repo_id".to_string(), "/repository/id".to_string()),
+                ("pusher".to_string(), "/pusher".to_string()),
+                ("missing".to_string(), "/does/not/exist".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let result = extract_parameters(&webhook, &github_payload()).unwrap();
+        assert_eq!(
+            result,
+            parameters(&[
+                ("ref", "refs/heads/main"),
+                ("sha", "abc123"),
+                ("distinct", "true"),
+                ("repo_id", "42"),
+            ])
+        );
+    }
+
+    #[test]
+    /// The parameters object is used by default and overwritten by mapped values
+    fn test_extract_default_parameters() {
+        let mut payload = github_payload();
+        payload.parameters = Some(parameters(&[("ref", "overwritten"), ("stage", "prod")]));
+
+        let result = extract_parameters(&Webhook::default(), &payload).unwrap();
+        assert_eq!(
+            result,
+            parameters(&[("ref", "overwritten"), ("stage", "prod")])
+        );
+
+        let webhook = Webhook {
+            parameter_mapping: HashMap::from([("ref".to_string(), "/ref".to_string())]),
+            ..Default::default()
+        };
+        let result = extract_parameters(&webhook, &payload).unwrap();
+        assert_eq!(
+            result,
+            parameters(&[("ref", "refs/heads/main"), ("stage", "prod")])
+        );
+    }
+
+    #[test]
+    /// Objects and arrays can't be used as parameters
+    fn test_extract_non_scalar() {
+        let webhook = Webhook {
+            parameter_mapping: HashMap::from([(
+                "repository".to_string(),
+                "/repository".to_string(),
+            )]),
+            ..Default::default()
+        };
+        assert!(extract_parameters(&webhook, &github_payload()).is_err());
+    }
+}
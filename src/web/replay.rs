@@ -0,0 +1,101 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::settings::Settings;
+
+/// A bounded cache of the delivery ids of recent requests, which is used to refuse duplicates.
+/// Once the cache is full, the oldest ids are evicted first.
+#[derive(Debug)]
+pub struct DeliveryCache {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl DeliveryCache {
+    pub fn new(capacity: usize) -> Self {
+        DeliveryCache {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Remember a delivery id. Returns `false`, if the id has already been seen.
+    pub fn insert(&mut self, id: &str) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.ids.insert(id.to_string()) {
+            return false;
+        }
+
+        self.order.push_back(id.to_string());
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+
+        true
+    }
+
+    /// Forget a delivery id, so the request can be retried.
+    pub fn remove(&mut self, id: &str) {
+        if self.ids.remove(id) {
+            self.order.retain(|other| other != id);
+        }
+    }
+}
+
+/// Get the delivery id of a request from the first configured header that's present.
+/// The id is prefixed with the webhook's name, as ids are only unique per sender.
+pub fn get_delivery_id(
+    settings: &Settings,
+    webhook_name: &str,
+    headers: &HashMap<String, String>,
+) -> Option<String> {
+    settings
+        .delivery_id_headers
+        .iter()
+        .find_map(|header| headers.get(&header.to_lowercase()))
+        .map(|id| format!("{webhook_name}:{id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Duplicates are detected until the id is evicted
+    fn test_delivery_cache() {
+        let mut cache = DeliveryCache::new(2);
+
+        assert!(cache.insert("a"));
+        assert!(!cache.insert("a"));
+        assert!(cache.insert("b"));
+
+        // `a` is the oldest id and is evicted
+        assert!(cache.insert("c"));
+        assert!(cache.insert("a"));
+        assert!(!cache.insert("c"));
+
+        cache.remove("c");
+        assert!(cache.insert("c"));
+    }
+
+    #[test]
+    /// Delivery ids are read from the configured headers
+    fn test_get_delivery_id() {
+        let settings = Settings {
+            delivery_id_headers: vec!["X-GitHub-Delivery".to_string()],
+            ..Default::default()
+        };
+        let headers = HashMap::from([("x-github-delivery".to_string(), "1234".to_string())]);
+
+        assert_eq!(
+            get_delivery_id(&settings, "deploy", &headers),
+            Some("deploy:1234".to_string())
+        );
+        assert_eq!(get_delivery_id(&settings, "deploy", &HashMap::new()), None);
+    }
+}
@@ -1,10 +1,43 @@
-use actix_web::{HttpRequest, HttpResponse, error::Error, http::Method, web};
-use pueue_lib::Request;
+use std::{collections::HashMap, time::Duration};
+
+use actix_web::{
+    HttpMessage,
+    HttpRequest,
+    HttpResponse,
+    error::{Error, ErrorConflict, ErrorInternalServerError, ErrorNotFound},
+    http::Method,
+    web,
+};
+use futures_util::stream;
+use pueue_lib::{
+    Client,
+    Request,
+    Response,
+    message::{StreamRequest, TaskSelection},
+};
 
 use crate::{
     internal_prelude::*,
-    pueue::get_pueue_client,
-    web::{AppState, Payload, authentication::verify_authentication_header, helper::*},
+    pueue::{get_pueue_client, get_task, get_task_log, wait_for_task},
+    settings::Webhook,
+    web::{
+        AppState,
+        FollowQuery,
+        LogFormat,
+        LogQuery,
+        Payload,
+        TaskCreated,
+        TaskInfo,
+        TaskLogInfo,
+        WebhookQuery,
+        authentication::verify_authentication_header,
+        client_cert::ClientIdentity,
+        filters::{check_filters, is_github_ping},
+        helper::*,
+        networks::verify_client_network,
+        parameters::extract_parameters,
+        replay::get_delivery_id,
+    },
 };
 
 // Index route for getting current state of the server
@@ -28,28 +61,90 @@ use crate::{
 pub async fn webhook(
     data: web::Data<AppState>,
     path_info: web::Path<String>,
+    query: web::Query<WebhookQuery>,
     request: HttpRequest,
     body: web::Bytes,
 ) -> Result<HttpResponse, Error> {
     let body: Vec<u8> = body.to_vec();
     let payload = match *request.method() {
-        Method::POST => get_payload(&body)?,
+        Method::POST => get_payload(&body, request.content_type())?,
         _ => Payload::default(),
     };
 
     let headers = get_headers_hash_map(request.headers())?;
     let webhook_name = path_info.into_inner();
 
-    // Check the credentials and signature headers of the request
-    verify_authentication_header(&data.settings, &headers, &body)?;
+    // Check the client's network and the credentials and signature headers of the request.
+    // Unknown webhooks are only reported to authenticated requests.
+    let webhook = data.settings.get_webhook_by_name(&webhook_name);
+    verify_client_network(
+        &data.settings,
+        webhook.as_ref().ok(),
+        request.peer_addr(),
+        &headers,
+    )?;
+    verify_authentication_header(
+        &data.settings,
+        webhook.as_ref().ok(),
+        &headers,
+        &body,
+        request.conn_data::<ClientIdentity>(),
+    )
+    .await?;
+    let webhook = webhook?;
+
+    // Refuse requests that have already been delivered.
+    let delivery_id = get_delivery_id(&data.settings, &webhook_name, &headers);
+    if let Some(id) = &delivery_id
+        && !data.delivery_ids.lock().unwrap().insert(id)
+    {
+        warn!("Refused duplicate delivery {id}");
+        return Err(ErrorConflict("Duplicate delivery"));
+    }
+
+    let response = process_webhook(&data, webhook, webhook_name, &query, &headers, &payload).await;
 
+    // Failed deliveries can be retried.
+    let succeeded = matches!(&response, Ok(response) if response.status().is_success());
+    if let Some(id) = &delivery_id
+        && !succeeded
+    {
+        data.delivery_ids.lock().unwrap().remove(id);
+    }
+
+    response
+}
+
+/// Turn an authenticated webhook request into a task.
+async fn process_webhook(
+    data: &AppState,
+    webhook: Webhook,
+    webhook_name: String,
+    query: &WebhookQuery,
+    headers: &HashMap<String, String>,
+    payload: &Payload,
+) -> Result<HttpResponse, Error> {
     info!("Incoming webhook for \"{webhook_name}\":");
     debug!("Got payload: {payload:?}");
 
+    let wait = should_wait(&webhook, query);
+
+    // Github sends a ping event when a webhook is created.
+    if is_github_ping(headers) {
+        info!("Got Github ping event");
+        return Ok(HttpResponse::Ok().body("pong"));
+    }
+
+    if let Some(reason) = check_filters(&webhook, headers, payload) {
+        info!("Skipping request: {reason}");
+        return Ok(HttpResponse::Accepted().body(format!("skipped: {reason}")));
+    }
+
     // Create a new task with the checked parameters and webhook name
-    let new_task = get_task_from_request(&data.settings, webhook_name, payload.parameters)?;
+    let parameters = extract_parameters(&webhook, payload)?;
+    let new_task = get_task_from_request(&data.settings, webhook_name, Some(parameters))?;
 
-    let mut client = match get_pueue_client(&data.settings).await {
+    let mut client = match get_pueue_client().await {
         Ok(client) => client,
         Err(err) => {
             return Ok(HttpResponse::InternalServerError()
@@ -57,10 +152,216 @@ pub async fn webhook(
         }
     };
 
+    let group = new_task.group.clone();
+    let command = new_task.command.clone();
     if let Err(err) = client.send_request(Request::Add(new_task)).await {
         return Ok(HttpResponse::InternalServerError()
             .body(format!("Failed to send message to Pueue daemon: {err:?}")));
     };
 
-    Ok(HttpResponse::Ok().finish())
+    let response = match client.receive_response().await {
+        Ok(response) => response,
+        Err(err) => {
+            return Ok(HttpResponse::InternalServerError().body(format!(
+                "Failed to receive response from Pueue daemon: {err:?}"
+            )));
+        }
+    };
+
+    let created = match get_created_task(response, group, command) {
+        Ok(created) => created,
+        Err(response) => return Ok(response),
+    };
+    if !wait {
+        return Ok(HttpResponse::Ok().json(created));
+    }
+
+    let timeout = Duration::from_secs(webhook.sync_timeout);
+    Ok(wait_for_task_output(&mut client, created, timeout).await)
+}
+
+/// Wait for a freshly created task to finish and respond with its output.
+/// If the task doesn't finish in time, respond with `202 Accepted`, so the caller can fall back to
+/// polling the task.
+async fn wait_for_task_output(
+    client: &mut Client,
+    created: TaskCreated,
+    timeout: Duration,
+) -> HttpResponse {
+    match wait_for_task(client, created.task_id, timeout).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return get_sync_response(created, None),
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to wait for task: {err:?}"));
+        }
+    }
+
+    match get_task_log(client, created.task_id, None).await {
+        Ok(Some(log)) => get_sync_response(created, Some(log)),
+        Ok(None) => HttpResponse::InternalServerError().body("Task vanished"),
+        Err(err) => HttpResponse::InternalServerError()
+            .body(format!("Failed to get log from Pueue daemon: {err:?}")),
+    }
+}
+
+/// Authenticate a request to the `/tasks` routes and get the requested task.
+///
+/// Tasks that weren't created by a webhook are treated as if they don't exist.
+/// Returns a client that's connected to the Pueue daemon and the task's info.
+async fn authorize_task_request(
+    data: &AppState,
+    request: &HttpRequest,
+    task_id: usize,
+) -> Result<(Client, TaskInfo), Error> {
+    let headers = get_headers_hash_map(request.headers())?;
+
+    // Check the client's network and the credentials and signature headers of the request.
+    // These requests don't have a body, so signatures cover the method and path instead.
+    // Otherwise, a signature would be valid for every task and for body-less webhook requests.
+    let signed_content = format!("{} {}", request.method(), request.path());
+    verify_client_network(&data.settings, None, request.peer_addr(), &headers)?;
+    verify_authentication_header(
+        &data.settings,
+        None,
+        &headers,
+        signed_content.as_bytes(),
+        request.conn_data::<ClientIdentity>(),
+    )
+    .await?;
+
+    let mut client = get_pueue_client().await.map_err(|err| {
+        ErrorInternalServerError(format!("Pueue daemon cannot be reached: {err:?}"))
+    })?;
+
+    let task = get_task(&mut client, task_id).await.map_err(|err| {
+        ErrorInternalServerError(format!("Failed to get state from Pueue daemon: {err:?}"))
+    })?;
+
+    match task.as_ref().and_then(get_task_info) {
+        Some(info) => Ok((client, info)),
+        None => Err(ErrorNotFound(format!("Can't find task with id: {task_id}"))),
+    }
+}
+
+/// Get the status of a task that has been created by a webhook.
+pub async fn task_status(
+    data: web::Data<AppState>,
+    path_info: web::Path<usize>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (_, info) = authorize_task_request(&data, &request, path_info.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(info))
+}
+
+/// Get the log output of a task that has been created by a webhook.
+pub async fn task_log(
+    data: web::Data<AppState>,
+    path_info: web::Path<usize>,
+    query: web::Query<LogQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let task_id = path_info.into_inner();
+    let (mut client, _) = authorize_task_request(&data, &request, task_id).await?;
+
+    let log = match get_task_log(&mut client, task_id, query.lines).await {
+        Ok(log) => log,
+        Err(err) => {
+            return Ok(HttpResponse::InternalServerError()
+                .body(format!("Failed to get log from Pueue daemon: {err:?}")));
+        }
+    };
+
+    // The task might've been removed in the meantime.
+    let Some((log, info)) = log.and_then(|log| get_task_info(&log.task).map(|info| (log, info)))
+    else {
+        return Err(ErrorNotFound(format!("Can't find task with id: {task_id}")));
+    };
+
+    match query.format {
+        LogFormat::Text => Ok(HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(log.output)),
+        LogFormat::Json => Ok(HttpResponse::Ok().json(TaskLogInfo {
+            task: info,
+            output: log.output,
+            output_complete: log.output_complete,
+        })),
+    }
+}
+
+/// Stream the output of a running task that has been created by a webhook via Server-Sent
+/// Events.
+///
+/// Output is sent as `output` events. Once the task finished, a final `finished` event with the
+/// task's status is sent and the stream is closed.
+pub async fn follow_task(
+    data: web::Data<AppState>,
+    path_info: web::Path<usize>,
+    query: web::Query<FollowQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let task_id = path_info.into_inner();
+    let (mut client, _) = authorize_task_request(&data, &request, task_id).await?;
+
+    let message = StreamRequest {
+        tasks: TaskSelection::TaskIds(vec![task_id]),
+        lines: query.lines,
+    };
+    if let Err(err) = client.send_request(message).await {
+        return Ok(HttpResponse::InternalServerError()
+            .body(format!("Failed to send message to Pueue daemon: {err:?}")));
+    };
+
+    let events = stream::unfold(Some(client), move |client| async move {
+        let mut client = client?;
+
+        let (event, next_state) = match client.receive_response().await {
+            Ok(Response::Stream(response)) => {
+                let output: String = response.logs.into_values().collect();
+                (format_sse_event("output", &output), Some(client))
+            }
+            Ok(Response::Close) => (get_finished_event(task_id).await, None),
+            Ok(Response::Failure(message)) => (format_sse_event("error", &message), None),
+            Ok(response) => {
+                warn!("Got unexpected response from Pueue daemon: {response:?}");
+                (
+                    format_sse_event("error", "Unexpected response from Pueue daemon"),
+                    None,
+                )
+            }
+            Err(err) => (
+                format_sse_event("error", &format!("Lost connection to Pueue daemon: {err}")),
+                None,
+            ),
+        };
+
+        Some((Ok::<_, Error>(web::Bytes::from(event)), next_state))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events))
+}
+
+/// Build the final event of a follow stream, which contains the status of the finished task.
+async fn get_finished_event(task_id: usize) -> String {
+    // The daemon closes the connection after the stream, so we need a new client.
+    let task = match get_pueue_client().await {
+        Ok(mut client) => get_task(&mut client, task_id).await,
+        Err(err) => Err(err),
+    };
+
+    match task {
+        Ok(task) => match task.as_ref().and_then(get_task_info) {
+            Some(info) => format_sse_event(
+                "finished",
+                &serde_json::to_string(&info).unwrap_or_default(),
+            ),
+            None => format_sse_event("error", "Task vanished"),
+        },
+        Err(err) => format_sse_event("error", &format!("Failed to get task status: {err}")),
+    }
 }